@@ -0,0 +1,407 @@
+use std::{
+    ffi::OsStr,
+    fs::{self, File, Permissions},
+    io::{self, Cursor, Read},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{PermissionsExt, symlink},
+    },
+    path::{Path, PathBuf},
+};
+
+use bzip2::read::MultiBzDecoder;
+use eyre::{Result, bail, eyre};
+use flate2::read::MultiGzDecoder;
+use liblzma::read::XzDecoder;
+use tar::EntryType;
+use zip::ZipArchive;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Unpack a tarball or a zip file into `dir` while reading it from `reader`,
+/// detecting the compression from the content
+pub fn unpack(mut reader: impl Read, dir: &Path) -> Result<()> {
+    let mut magic = Vec::with_capacity(6);
+    (&mut reader).take(6).read_to_end(&mut magic)?;
+    let reader = Cursor::new(magic.clone()).chain(reader);
+
+    match magic[..] {
+        [b'P', b'K', 3, 4, ..] | [b'P', b'K', 5, 6, ..] => unpack_zip(reader, dir),
+        [0x1f, 0x8b, ..] => unpack_tar(MultiGzDecoder::new(reader), dir),
+        [b'B', b'Z', b'h', ..] => unpack_tar(MultiBzDecoder::new(reader), dir),
+        [0xfd, b'7', b'z', b'X', b'Z', 0, ..] => {
+            unpack_tar(XzDecoder::new_multi_decoder(reader), dir)
+        }
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => unpack_tar(zstd::Decoder::new(reader)?, dir),
+        _ => unpack_tar(reader, dir),
+    }
+}
+
+/// The path to hash after stripping the top level directory of `dir` like fetchzip does by
+/// default, a single top level file is kept in `dir`
+pub fn strip_root(dir: &Path) -> Result<PathBuf> {
+    let mut entries = fs::read_dir(dir)?;
+    let (Some(entry), None) = (entries.next().transpose()?, entries.next()) else {
+        bail!("archive must contain a single file or directory");
+    };
+
+    Ok(if entry.file_type()?.is_dir() {
+        entry.path()
+    } else {
+        dir.to_owned()
+    })
+}
+
+fn unpack_tar(reader: impl Read, dir: &Path) -> Result<()> {
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path_bytes().into_owned();
+
+        match entry.header().entry_type() {
+            EntryType::Regular | EntryType::Continuous => {
+                let executable = entry.header().mode()? & 0o100 != 0;
+                write_file(&prepare(dir, &path)?, &mut entry, executable)?;
+            }
+
+            EntryType::Directory => {
+                mkdir(dir, components(&path)?)?;
+            }
+
+            EntryType::Symlink => {
+                let target = entry
+                    .link_name_bytes()
+                    .ok_or_else(|| eyre!("symlink without a target"))?;
+                symlink(OsStr::from_bytes(&target), prepare(dir, &path)?)?;
+            }
+
+            EntryType::Link => {
+                let target = entry
+                    .link_name_bytes()
+                    .ok_or_else(|| eyre!("hard link without a target"))?;
+                let source = lookup(dir, &target)?.ok_or_else(|| {
+                    eyre!(
+                        "hard link to nonexistent file {}",
+                        String::from_utf8_lossy(&target),
+                    )
+                })?;
+                fs::hard_link(source, prepare(dir, &path)?)?;
+            }
+
+            EntryType::XGlobalHeader | EntryType::XHeader => {}
+
+            kind => bail!(
+                "unsupported entry type {kind:?} for {}",
+                String::from_utf8_lossy(&path),
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+// zip files are read from the end, so they are saved to a temporary file first
+fn unpack_zip(mut reader: impl Read, dir: &Path) -> Result<()> {
+    let mut file = tempfile::tempfile()?;
+    io::copy(&mut reader, &mut file)?;
+    let mut archive = ZipArchive::new(file)?;
+
+    for i in 0 .. archive.len() {
+        let mut file = archive.by_index(i)?;
+        let path = file.name_raw().to_vec();
+
+        if file.is_dir() {
+            mkdir(dir, components(&path)?)?;
+            continue;
+        }
+
+        let mode = file.unix_mode().unwrap_or(0o644);
+        if mode & S_IFMT == S_IFLNK {
+            let mut target = Vec::new();
+            file.read_to_end(&mut target)?;
+            symlink(OsStr::from_bytes(&target), prepare(dir, &path)?)?;
+        } else {
+            write_file(&prepare(dir, &path)?, &mut file, mode & 0o100 != 0)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_file(path: &Path, contents: &mut impl Read, executable: bool) -> Result<()> {
+    let mut file = File::create(path)?;
+    io::copy(contents, &mut file)?;
+    file.set_permissions(Permissions::from_mode(if executable {
+        0o755
+    } else {
+        0o644
+    }))?;
+    Ok(())
+}
+
+fn components(path: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut components = Vec::new();
+    for component in path.split(|&c| c == b'/') {
+        match component {
+            b"" | b"." => {}
+            b".." => bail!(
+                "path escapes the archive: {}",
+                String::from_utf8_lossy(path),
+            ),
+            component => components.push(component),
+        }
+    }
+    Ok(components)
+}
+
+// directories replace what is in their way, so nothing is written outside of `dir` through
+// symlinks in the archive
+fn mkdir<'a>(dir: &Path, components: impl IntoIterator<Item = &'a [u8]>) -> Result<PathBuf> {
+    let mut dest = dir.to_owned();
+    for component in components {
+        dest.push(OsStr::from_bytes(component));
+        match fs::symlink_metadata(&dest) {
+            Ok(metadata) if metadata.is_dir() => continue,
+            Ok(_) => fs::remove_file(&dest)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        fs::create_dir(&dest)?;
+    }
+    Ok(dest)
+}
+
+// the path of a new entry, creating its parent directories and replacing earlier entries
+fn prepare(dir: &Path, path: &[u8]) -> Result<PathBuf> {
+    let mut components = components(path)?;
+    let name = components
+        .pop()
+        .ok_or_else(|| eyre!("empty path in archive"))?;
+
+    let dest = mkdir(dir, components)?.join(OsStr::from_bytes(name));
+    match fs::symlink_metadata(&dest) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&dest)?,
+        Ok(_) => fs::remove_file(&dest)?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(dest)
+}
+
+// files that were already unpacked, without following symlinks
+fn lookup(dir: &Path, path: &[u8]) -> Result<Option<PathBuf>> {
+    let mut dest = dir.to_owned();
+    let components = components(path)?;
+    for (i, component) in components.iter().enumerate() {
+        dest.push(OsStr::from_bytes(component));
+        match fs::symlink_metadata(&dest) {
+            Ok(metadata) if metadata.is_dir() == (i + 1 < components.len()) => {}
+            _ => return Ok(None),
+        }
+    }
+    Ok((!components.is_empty()).then_some(dest))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Cursor, Write},
+        os::unix::fs::PermissionsExt,
+        path::Path,
+    };
+
+    use bzip2::write::BzEncoder;
+    use flate2::{Compression, write::GzEncoder};
+    use liblzma::write::XzEncoder;
+    use tar::{Builder, EntryType, Header};
+    use tempfile::TempDir;
+    use zip::{ZipWriter, write::SimpleFileOptions};
+
+    use super::{strip_root, unpack};
+    use crate::nar::hash_path;
+
+    fn hash(data: &[u8]) -> String {
+        let dir = TempDir::new().unwrap();
+        unpack(data, dir.path()).unwrap();
+        hash_path(&strip_root(dir.path()).unwrap(), &|_| false).unwrap()
+    }
+
+    fn is_executable(path: &Path) -> bool {
+        fs::metadata(path).unwrap().permissions().mode() & 0o100 != 0
+    }
+
+    fn tar() -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_size(0);
+        builder
+            .append_data(&mut header, "nurl-0.1.0/", &[][..])
+            .unwrap();
+
+        let mut header = Header::new_gnu();
+        header.set_mode(0o755);
+        header.set_size(10);
+        builder
+            .append_data(&mut header, "nurl-0.1.0/bin/nurl", &b"#!/bin/sh\n"[..])
+            .unwrap();
+
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(5);
+        builder
+            .append_data(&mut header, "./nurl-0.1.0/README", &b"nurl\n"[..])
+            .unwrap();
+
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "nurl-0.1.0/nurl", "bin/nurl")
+            .unwrap();
+
+        builder.into_inner().unwrap()
+    }
+
+    fn tar_gz() -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&tar()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zip() -> Vec<u8> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+
+        zip.add_directory("nurl-0.1.0/bin/", options).unwrap();
+        zip.start_file("nurl-0.1.0/bin/nurl", options.unix_permissions(0o755))
+            .unwrap();
+        zip.write_all(b"#!/bin/sh\n").unwrap();
+        zip.start_file("nurl-0.1.0/README", options.unix_permissions(0o644))
+            .unwrap();
+        zip.write_all(b"nurl\n").unwrap();
+        zip.add_symlink("nurl-0.1.0/nurl", "bin/nurl", options)
+            .unwrap();
+
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn same_hash() {
+        assert_eq!(hash(&tar_gz()), hash(&zip()));
+    }
+
+    #[test]
+    fn multiple_roots() {
+        let mut builder = Builder::new(Vec::new());
+        for path in ["a", "b"] {
+            let mut header = Header::new_gnu();
+            header.set_mode(0o644);
+            header.set_size(0);
+            builder.append_data(&mut header, path, &[][..]).unwrap();
+        }
+
+        let dir = TempDir::new().unwrap();
+        unpack(&builder.into_inner().unwrap()[..], dir.path()).unwrap();
+        assert!(strip_root(dir.path()).is_err());
+    }
+
+    #[test]
+    fn compressions() {
+        let mut bz2 = BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bz2.write_all(&tar()).unwrap();
+        let mut xz = XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&tar()).unwrap();
+        let zstd = zstd::encode_all(&tar()[..], 0).unwrap();
+
+        let expected = hash(&tar());
+        for data in [tar_gz(), bz2.finish().unwrap(), xz.finish().unwrap(), zstd] {
+            assert_eq!(hash(&data), expected);
+        }
+    }
+
+    #[test]
+    fn executable() {
+        for data in [tar(), zip()] {
+            let dir = TempDir::new().unwrap();
+            unpack(&data[..], dir.path()).unwrap();
+            let root = strip_root(dir.path()).unwrap();
+            assert!(is_executable(&root.join("bin/nurl")));
+            assert!(!is_executable(&root.join("README")));
+            assert_eq!(
+                fs::read_link(root.join("nurl")).unwrap(),
+                Path::new("bin/nurl")
+            );
+        }
+    }
+
+    #[test]
+    fn single_file() {
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_mode(0o755);
+        header.set_size(10);
+        builder
+            .append_data(&mut header, "nurl", &b"#!/bin/sh\n"[..])
+            .unwrap();
+
+        let dir = TempDir::new().unwrap();
+        unpack(&builder.into_inner().unwrap()[..], dir.path()).unwrap();
+
+        // the file is kept in the top level directory
+        assert_eq!(strip_root(dir.path()).unwrap(), dir.path());
+        assert!(is_executable(&dir.path().join("nurl")));
+    }
+
+    #[test]
+    fn links() {
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_mode(0o755);
+        header.set_size(5);
+        builder
+            .append_data(&mut header, "src/bin/a", &b"nurl\n"[..])
+            .unwrap();
+        for (kind, path, target) in [
+            (EntryType::Link, "src/b", "src/bin/a"),
+            (EntryType::Symlink, "src/c", "bin/a"),
+            // symlinks are replaced instead of written through
+            (EntryType::Symlink, "src/d", ".."),
+        ] {
+            let mut header = Header::new_gnu();
+            header.set_entry_type(kind);
+            header.set_size(0);
+            builder.append_link(&mut header, path, target).unwrap();
+        }
+        let mut header = Header::new_gnu();
+        header.set_mode(0o644);
+        header.set_size(0);
+        builder
+            .append_data(&mut header, "src/d/e", &[][..])
+            .unwrap();
+
+        let dir = TempDir::new().unwrap();
+        unpack(&builder.into_inner().unwrap()[..], dir.path()).unwrap();
+        let root = strip_root(dir.path()).unwrap();
+
+        assert_eq!(fs::read(root.join("b")).unwrap(), b"nurl\n");
+        assert!(is_executable(&root.join("b")));
+        assert_eq!(fs::read_link(root.join("c")).unwrap(), Path::new("bin/a"));
+        assert!(root.join("d").is_dir());
+        assert!(!dir.path().join("e").exists());
+
+        let mut builder = Builder::new(Vec::new());
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::Link);
+        header.set_size(0);
+        builder.append_link(&mut header, "src/b", "src/a").unwrap();
+
+        let dir = TempDir::new().unwrap();
+        assert!(unpack(&builder.into_inner().unwrap()[..], dir.path()).is_err());
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+use clap::{Parser, ValueEnum};
+use eyre::{Result, bail, eyre};
+use itertools::Itertools;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    Inference, cli::FetcherFunction, config::FetcherConfig, output::FetcherOutput, prefetch,
+    prefetch::with_tag,
+};
+
+#[derive(Parser)]
+#[command(no_binary_name = true)]
+struct Line {
+    url: String,
+
+    rev: Option<String>,
+
+    #[arg(short = 'S', long, num_args=0..=1, require_equals = true, default_missing_value = "true")]
+    submodules: Option<bool>,
+
+    #[arg(long)]
+    private: bool,
+
+    #[arg(short, long)]
+    fetcher: Option<FetcherFunction>,
+
+    #[arg(short, long = "arg", num_args = 2)]
+    args: Vec<String>,
+
+    #[arg(short = 'A', long = "arg-str", num_args = 2)]
+    args_str: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonLine {
+    url: String,
+    #[serde(default)]
+    rev: Option<String>,
+    #[serde(default)]
+    submodules: Option<bool>,
+    #[serde(default)]
+    private: bool,
+    #[serde(default)]
+    fetcher: Option<String>,
+    #[serde(default)]
+    args: FxHashMap<String, Value>,
+}
+
+#[derive(Serialize)]
+struct Record {
+    line: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(flatten)]
+    result: RecordResult,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum RecordResult {
+    Ok(FetcherOutput),
+    Err { error: String },
+}
+
+struct Item {
+    url: String,
+    fetcher: Option<FetcherFunction>,
+    cfg: FetcherConfig,
+}
+
+/// Prefetch every item in `input` with up to `cfg.jobs` items at a time,
+/// writing one JSON record per item to `out` in the order of the input
+///
+/// Errors from individual items are written as records instead of being returned,
+/// the number of failed items is returned instead
+pub fn run(
+    input: impl BufRead,
+    out: &mut impl Write,
+    inference: &Inference,
+    cfg: &FetcherConfig,
+) -> Result<usize> {
+    let mut lines = Vec::new();
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if !(line.is_empty() || line.starts_with('#')) {
+            lines.push((i + 1, line.to_owned()));
+        }
+    }
+
+    let next = &AtomicUsize::new(0);
+    let lines = &lines;
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|s| {
+        for _ in 0 .. cfg.jobs.get().min(lines.len()) {
+            let tx = tx.clone();
+            s.spawn(move || {
+                while let Some((line, item)) = lines.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let record =
+                        with_tag(line.to_string(), || process(*line, item, inference, cfg));
+                    if tx.send((*line, record)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        let mut failures = 0;
+        let mut pending = BTreeMap::new();
+        let mut lines = lines.iter().map(|(line, _)| *line);
+        let mut expected = lines.next();
+
+        for (line, record) in rx {
+            pending.insert(line, record);
+
+            while let Some(record) = expected.and_then(|line| pending.remove(&line)) {
+                if matches!(record.result, RecordResult::Err { .. }) {
+                    failures += 1;
+                }
+
+                serde_json::to_writer(&mut *out, &record)?;
+                writeln!(out)?;
+                out.flush()?;
+
+                expected = lines.next();
+            }
+        }
+
+        Ok(failures)
+    })
+}
+
+fn process(line: usize, item: &str, inference: &Inference, cfg: &FetcherConfig) -> Record {
+    let (url, result) = match parse_line(item, cfg) {
+        Ok(Item { url, fetcher, cfg }) => {
+            let result = prefetch(&url, fetcher, inference, cfg);
+            (Some(url), result)
+        }
+        Err(e) => (None, Err(e)),
+    };
+
+    Record {
+        line,
+        url,
+        result: match result {
+            Ok(output) => RecordResult::Ok(output),
+            Err(e) => RecordResult::Err {
+                error: format!("{e:#}"),
+            },
+        },
+    }
+}
+
+fn parse_line(line: &str, base: &FetcherConfig) -> Result<Item> {
+    let mut cfg = base.clone();
+
+    if line.starts_with('{') {
+        let line: JsonLine = serde_json::from_str(line)?;
+
+        cfg.rev = line.rev;
+        cfg.submodules = line.submodules;
+        cfg.private |= line.private;
+        for (key, value) in line.args {
+            match value {
+                Value::String(value) => {
+                    cfg.args_str.insert(key, value);
+                }
+                Value::Bool(value) => {
+                    cfg.args.insert(key, value.to_string());
+                }
+                Value::Object(mut value)
+                    if value.get("type").and_then(Value::as_str) == Some("nix") =>
+                {
+                    let Some(Value::String(value)) = value.remove("value") else {
+                        bail!("invalid value for argument {key}");
+                    };
+                    cfg.args.insert(key, value);
+                }
+                _ => bail!("invalid value for argument {key}"),
+            }
+        }
+
+        return Ok(Item {
+            url: line.url,
+            fetcher: line
+                .fetcher
+                .map(|fetcher| FetcherFunction::from_str(&fetcher, false))
+                .transpose()
+                .map_err(|e| eyre!(e))?,
+            cfg,
+        });
+    }
+
+    let line = Line::try_parse_from(split_words(line)?).map_err(|e| {
+        // only keep the first line of clap's error message, which does not include usage
+        let e = e.to_string();
+        let e = e.lines().next().unwrap_or_default();
+        eyre!("{}", e.strip_prefix("error: ").unwrap_or(e))
+    })?;
+
+    cfg.rev = line.rev;
+    cfg.submodules = line.submodules;
+    cfg.private |= line.private;
+    cfg.args.extend(line.args.into_iter().tuples());
+    cfg.args_str.extend(line.args_str.into_iter().tuples());
+
+    Ok(Item {
+        url: line.url,
+        fetcher: line.fetcher,
+        cfg,
+    })
+}
+
+// split a line into words like a shell would, without any expansions
+fn split_words(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' => {
+                words.extend(word.take());
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated single quote"),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => bail!("unterminated double quote"),
+                        },
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated double quote"),
+                    }
+                }
+            }
+            '\\' => {
+                let word = word.get_or_insert_with(String::new);
+                if let Some(c) = chars.next() {
+                    word.push(c);
+                }
+            }
+            c => {
+                word.get_or_insert_with(String::new).push(c);
+            }
+        }
+    }
+
+    words.extend(word);
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_words;
+
+    #[test]
+    fn words() {
+        assert_eq!(
+            split_words(r#"https://example.org  v1 --arg-str a 'b c' -a d "\"e\" f""#).unwrap(),
+            [
+                "https://example.org",
+                "v1",
+                "--arg-str",
+                "a",
+                "b c",
+                "-a",
+                "d",
+                r#""e" f"#,
+            ],
+        );
+    }
+
+    #[test]
+    fn empty_quotes() {
+        assert_eq!(split_words("a '' b").unwrap(), ["a", "", "b"]);
+    }
+
+    #[test]
+    fn unterminated() {
+        assert!(split_words("a 'b").is_err());
+        assert!(split_words(r#"a "b"#).is_err());
+    }
+}
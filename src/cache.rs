@@ -0,0 +1,212 @@
+use std::{
+    env,
+    fmt::Write as _,
+    fs::{self, File},
+    io,
+    path::PathBuf,
+    process,
+    time::{Duration, SystemTime},
+};
+
+use eyre::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// `$XDG_CACHE_HOME/nurl`, or `None` if neither `$XDG_CACHE_HOME` nor `$HOME` is set
+pub fn cache_dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".cache"),
+    };
+    Some(dir.join("nurl"))
+}
+
+/// Hashes of immutable sources stored under `$XDG_CACHE_HOME/nurl`,
+/// one file per entry named after the hash of the key
+#[derive(Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    refresh: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Entry {
+    pub key: String,
+    pub hash: String,
+}
+
+impl Cache {
+    /// With `refresh`, entries are overwritten without being read
+    pub fn new(refresh: bool) -> Option<Self> {
+        Some(Self::in_dir(cache_dir()?.join("hashes"), refresh))
+    }
+
+    fn in_dir(dir: PathBuf, refresh: bool) -> Self {
+        Self { dir, refresh }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let mut name = String::new();
+        for byte in Sha256::digest(key) {
+            let _ = write!(name, "{byte:02x}");
+        }
+        self.dir.join(name)
+    }
+
+    pub fn get(&self, key: &str) -> Option<String> {
+        if self.refresh {
+            return None;
+        }
+
+        let path = self.path(key);
+        let entry: Entry = serde_json::from_slice(&fs::read(&path).ok()?).ok()?;
+        if entry.key != key {
+            return None;
+        }
+
+        // the modification time is used as the last access time for gc
+        let _ = File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()));
+
+        Some(entry.hash)
+    }
+
+    pub fn insert(&self, key: String, hash: String) -> Result<()> {
+        let path = self.path(&key);
+        fs::create_dir_all(&self.dir)?;
+
+        // write to a temporary file first, so concurrent readers never see partial entries
+        let tmp = path.with_extension(format!("tmp{}", process::id()));
+        fs::write(&tmp, serde_json::to_vec(&Entry { key, hash })?)?;
+        fs::rename(tmp, path)?;
+
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for file in self.read_dir()? {
+            if let Ok(entry) = serde_json::from_slice(&fs::read(file?.path())?) {
+                entries.push(entry);
+            }
+        }
+        entries.sort_unstable_by(|x: &Entry, y| x.key.cmp(&y.key));
+        Ok(entries)
+    }
+
+    /// Remove entries that have not been used for `max_age`,
+    /// returning the number of removed entries
+    pub fn gc(&self, max_age: Duration) -> Result<usize> {
+        let now = SystemTime::now();
+        let mut removed = 0;
+
+        for file in self.read_dir()? {
+            let file = file?;
+            let modified = file.metadata()?.modified()?;
+            if now.duration_since(modified).unwrap_or_default() > max_age {
+                fs::remove_file(file.path())?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    fn read_dir(&self) -> Result<Vec<io::Result<fs::DirEntry>>> {
+        match fs::read_dir(&self.dir) {
+            Ok(files) => Ok(files.collect()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        time::{Duration, SystemTime},
+    };
+
+    use tempfile::TempDir;
+
+    use super::Cache;
+
+    #[test]
+    fn get_insert() {
+        let dir = TempDir::new().unwrap();
+        let cache = Cache::in_dir(dir.path().join("nurl/hashes"), false);
+
+        assert_eq!(cache.get("fetchgit{rev=\"a\";}"), None);
+        cache
+            .insert("fetchgit{rev=\"a\";}".into(), "sha256-a".into())
+            .unwrap();
+        cache
+            .insert("fetchgit{rev=\"b\";}".into(), "sha256-b".into())
+            .unwrap();
+        assert_eq!(
+            cache.get("fetchgit{rev=\"a\";}").as_deref(),
+            Some("sha256-a")
+        );
+        assert_eq!(
+            cache.get("fetchgit{rev=\"b\";}").as_deref(),
+            Some("sha256-b")
+        );
+
+        cache
+            .insert("fetchgit{rev=\"a\";}".into(), "sha256-c".into())
+            .unwrap();
+        let entries: Vec<_> = cache
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.key, entry.hash))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                ("fetchgit{rev=\"a\";}".into(), "sha256-c".into()),
+                ("fetchgit{rev=\"b\";}".into(), "sha256-b".into()),
+            ],
+        );
+    }
+
+    #[test]
+    fn refresh() {
+        let dir = TempDir::new().unwrap();
+        let hashes = dir.path().join("nurl/hashes");
+        Cache::in_dir(hashes.clone(), false)
+            .insert("key".into(), "sha256-old".into())
+            .unwrap();
+
+        let cache = Cache::in_dir(hashes.clone(), true);
+        assert_eq!(cache.get("key"), None);
+        cache.insert("key".into(), "sha256-new".into()).unwrap();
+
+        let cache = Cache::in_dir(hashes, false);
+        assert_eq!(cache.get("key").as_deref(), Some("sha256-new"));
+    }
+
+    #[test]
+    fn gc() {
+        let dir = TempDir::new().unwrap();
+        let cache = Cache::in_dir(dir.path().join("nurl/hashes"), false);
+        assert_eq!(cache.gc(Duration::ZERO).unwrap(), 0);
+
+        cache.insert("old".into(), "sha256-old".into()).unwrap();
+        cache.insert("new".into(), "sha256-new".into()).unwrap();
+        let month = Duration::from_secs(30 * 24 * 60 * 60);
+        File::options()
+            .append(true)
+            .open(cache.path("old"))
+            .unwrap()
+            .set_modified(SystemTime::now() - 2 * month)
+            .unwrap();
+
+        assert_eq!(cache.gc(month).unwrap(), 1);
+        assert_eq!(cache.get("old"), None);
+        assert_eq!(cache.get("new").as_deref(), Some("sha256-new"));
+    }
+}
@@ -1,11 +1,23 @@
-use clap::{Parser, ValueEnum};
+use std::{num::NonZeroUsize, path::PathBuf};
+
+use clap::{Parser, Subcommand, ValueEnum};
 
 /// Generate Nix fetcher calls from repository URLs
 /// https://github.com/nix-community/nurl
 #[derive(Parser)]
-#[command(version, verbatim_doc_comment)]
+#[command(
+    version,
+    verbatim_doc_comment,
+    args_conflicts_with_subcommands = true,
+    disable_help_subcommand = true,
+    subcommand_negates_reqs = true
+)]
 pub struct Opts {
-    /// URL to the repository to be fetched
+    #[command(subcommand)]
+    pub subcommand: Option<Command>,
+
+    /// URL to the repository to be fetched, which can also point to the revision
+    /// like https://github.com/owner/repo/tree/v1.2 or git+https://example.org/repo?ref=main
     #[arg(
         required_unless_present = "command",
         default_value_t, // placeholder, will not be accessed
@@ -13,13 +25,41 @@ pub struct Opts {
     )]
     pub url: String,
 
-    /// The revision or reference to be fetched
+    /// The revision or reference to be fetched, or a version requirement like '^1.4'
+    /// resolved against versions on crates.io, PyPI, and hex.pm or tags on git forges
     pub rev: Option<String>,
 
+    /// Fetch the tag with the highest version instead of the latest commit
+    /// when the revision is not specified
+    #[arg(long, conflicts_with_all = ["rev", "latest_release"])]
+    pub latest_tag: bool,
+
+    /// Fetch the tag of the release with the highest version instead of the latest commit
+    /// when the revision is not specified
+    #[arg(long, conflicts_with = "rev")]
+    pub latest_release: bool,
+
+    /// Only consider tags matching the regex, using the first capture group as the version,
+    /// implies --latest-tag when neither the revision nor --latest-release is specified
+    #[arg(long, value_name = "REGEX")]
+    pub tag_pattern: Option<String>,
+
+    /// Consider pre-releases with --latest-tag and --latest-release
+    #[arg(long)]
+    pub prerelease: bool,
+
     /// Fetch submodules instead of using the fetcher's default
     #[arg(short = 'S', long, num_args=0..=1, require_equals = true, default_missing_value = "true")]
     pub submodules: Option<bool>,
 
+    /// Fetch a private repository on GitHub, adding `private = true` to the output
+    ///
+    /// Credentials for API requests and prefetching are read from NURL_TOKEN_<HOST>,
+    /// variables like GH_TOKEN and GITLAB_TOKEN, the tokens table of the config file,
+    /// ~/.netrc, or git credential helpers
+    #[arg(long)]
+    pub private: bool,
+
     /// Specify the fetcher function instead of inferring from the URL
     #[arg(short, long)]
     pub fetcher: Option<FetcherFunction>,
@@ -28,10 +68,40 @@ pub struct Opts {
     #[arg(short = 'F', long, default_value = "fetchgit")]
     pub fallback: FetcherFunction,
 
+    /// Probe the APIs of unknown hosts to detect GitLab, Gitea, Forgejo,
+    /// or GitHub Enterprise Server instances before falling back
+    #[arg(short, long)]
+    pub detect: bool,
+
     /// Path to nixpkgs (in nix)
     #[arg(short, long, default_value = "<nixpkgs>")]
     pub nixpkgs: String,
 
+    /// How to compute hashes
+    #[arg(short = 'B', long, default_value = "nix")]
+    pub backend: Backend,
+
+    /// Record the hashes, looked up refs and heads, GitLab projects, and pinned pull requests
+    /// to a JSON file, keeping what was already recorded
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Use the hashes, refs, heads, projects, and pull requests recorded with --record
+    /// instead of computing them,
+    /// failing on anything that was not recorded
+    #[arg(long, value_name = "FILE", env = "NURL_REPLAY")]
+    pub replay: Option<PathBuf>,
+
+    /// Do not read or write cached hashes
+    ///
+    /// Hashes are only cached for commit hashes and versions
+    #[arg(long, conflicts_with = "refresh")]
+    pub no_cache: bool,
+
+    /// Compute hashes even if they are cached, updating the cache
+    #[arg(long)]
+    pub refresh: bool,
+
     /// Extra indentation (in number of spaces)
     #[arg(short, long, default_value_t = 0)]
     pub indent: usize,
@@ -96,6 +166,50 @@ pub struct Opts {
     #[arg(short, long, group = "command")]
     pub expr: Option<String>,
 
+    /// Prefetch every URL listed in a file ('-' for stdin),
+    /// output one JSON object per line
+    ///
+    /// Each line is either a JSON object with a url, rev, fetcher, submodules, private, and args,
+    /// or a URL followed by an optional revision
+    /// and --fetcher, --submodules, --private, --arg, or --arg-str
+    ///
+    /// Examples:
+    /// {n}  https://github.com/nix-community/nurl v0.3.0
+    /// {n}  https://github.com/Koihik/LuaFormatter 1.3.0 --arg fetchSubmodules true
+    /// {n}  {"url": "https://github.com/nix-community/nurl", "rev": "v0.3.0"}
+    #[arg(short, long, group = "command", value_name = "FILE")]
+    pub batch: Option<String>,
+
+    /// Maximum number of items to prefetch concurrently with --batch,
+    /// pages of tags to request concurrently, and submodules to clone concurrently
+    /// with --backend native, defaults to the number of available CPUs
+    #[arg(long, value_name = "N")]
+    pub jobs: Option<NonZeroUsize>,
+
+    /// Update the revisions and hashes of fetcher calls in a Nix file in place,
+    /// to the latest revision unless --update-rev is specified
+    ///
+    /// Calls that use a tag are updated to the latest tag instead of the latest commit.
+    /// Revisions like `tag = "v${version}";` update the string literal bound to the variable
+    /// elsewhere in the file, other arguments are passed to the fetcher as is
+    #[arg(short, long, group = "command", value_name = "FILE")]
+    pub update: Option<PathBuf>,
+
+    /// Only update the fetcher call on the specified line,
+    /// only used when --update is specified
+    #[arg(long, value_name = "LINE", conflicts_with = "update_attr")]
+    pub update_line: Option<usize>,
+
+    /// Only update fetcher calls bound to the specified attribute (e.g. src),
+    /// only used when --update is specified
+    #[arg(long, value_name = "ATTR")]
+    pub update_attr: Option<String>,
+
+    /// The revision to update to instead of the latest revision,
+    /// only used when --update is specified
+    #[arg(long, value_name = "REV")]
+    pub update_rev: Option<String>,
+
     /// List all available fetchers
     #[arg(short, long, group = "command")]
     pub list_fetchers: bool,
@@ -115,6 +229,26 @@ pub struct Opts {
     pub list_sep: Option<String>,
 }
 
+#[derive(Subcommand)]
+pub enum Command {
+    /// Manage the cached hashes
+    #[command(subcommand)]
+    Cache(CacheCommand),
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// List the cached hashes
+    List,
+
+    /// Remove cached hashes that have not been used recently
+    Gc {
+        /// Remove hashes that have not been used for this many days
+        #[arg(long, value_name = "DAYS", default_value_t = 30)]
+        max_age: u64,
+    },
+}
+
 #[derive(Clone, Copy, Debug, ValueEnum)]
 #[clap(rename_all = "camelCase")]
 pub enum FetcherFunction {
@@ -138,3 +272,16 @@ pub enum FetcherFunction {
     Fetchurl,
     Fetchzip,
 }
+
+#[derive(Clone, Copy, Debug, Default, ValueEnum)]
+pub enum Backend {
+    /// Use nix for everything
+    #[default]
+    Nix,
+    /// Download and hash sources without nix when possible,
+    /// falling back to nix otherwise, git repositories are cloned with git from PATH
+    Native,
+    /// Print the nix commands that would be run and output fake hashes,
+    /// still looking up revisions, tags, and pull requests over the network
+    DryRun,
+}
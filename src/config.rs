@@ -1,17 +1,45 @@
-use std::io::Write;
+use std::{num::NonZeroUsize, sync::Arc, thread};
 
-use eyre::Result;
+use eyre::{Result, WrapErr};
 use itertools::Itertools;
+use regex::Regex;
 use rustc_hash::FxHashMap;
-use serde_json::{Value, json};
 
-use crate::cli::Opts;
+use crate::{
+    cache::Cache,
+    cli::{Backend, Opts},
+    fetcher::PatchPage,
+    ls_refs::RefKind,
+    output::{Arg, FetcherOutput},
+    prefetch::{DryRun, Fixtures, Native, Nix, Prefetcher, Source},
+};
 
+/// What to fetch when no revision is specified
+#[derive(Clone, Copy, Default)]
+pub enum Latest {
+    /// The latest commit on the default branch
+    #[default]
+    Commit,
+    /// The tag with the highest version
+    Tag,
+    /// The tag of the release with the highest version
+    Release,
+}
+
+#[derive(Clone)]
 pub struct FetcherConfig {
     pub rev: Option<String>,
     pub submodules: Option<bool>,
+    pub private: bool,
+    pub latest: Latest,
+    pub prerelease: bool,
+    pub tag_pattern: Option<Regex>,
     pub nixpkgs: String,
+    pub prefetcher: Arc<dyn Prefetcher>,
+    pub cache: Option<Cache>,
     pub indent: usize,
+    /// The maximum number of concurrent prefetches and requests
+    pub jobs: NonZeroUsize,
     pub args: FxHashMap<String, String>,
     pub args_str: FxHashMap<String, String>,
     pub overwrites: FxHashMap<String, String>,
@@ -21,6 +49,56 @@ pub struct FetcherConfig {
 }
 
 impl FetcherConfig {
+    pub fn prefetch(&self, source: Source) -> Result<String> {
+        self.prefetcher.prefetch(&source)
+    }
+
+    pub fn resolve_ref(&self, url: &str, rev: &str) -> Result<RefKind> {
+        self.prefetcher.resolve_ref(url, rev)
+    }
+
+    pub fn head(&self, url: &str) -> Result<String> {
+        self.prefetcher.head(url)
+    }
+
+    pub fn gitlab_project(&self, host: &str, path: &str) -> Result<Option<String>> {
+        self.prefetcher.gitlab_project(host, path)
+    }
+
+    pub fn pin_patch(&self, page: &PatchPage) -> Result<Vec<String>> {
+        self.prefetcher.pin_patch(page)
+    }
+
+    /// The version part of `tag`, which is the first capture group of `--tag-pattern`,
+    /// or `None` if the tag does not match the pattern
+    pub fn tag_version<'a>(&self, tag: &'a str) -> Option<&'a str> {
+        let Some(pattern) = &self.tag_pattern else {
+            return Some(tag);
+        };
+        let captures = pattern.captures(tag)?;
+        Some(captures.get(1).or_else(|| captures.get(0))?.as_str())
+    }
+
+    /// Get the hash from the cache if `key` is not `None`, otherwise compute it with `f`
+    pub fn cached(
+        &self,
+        key: Option<String>,
+        f: impl FnOnce() -> Result<String>,
+    ) -> Result<String> {
+        let (Some(cache), Some(key)) = (&self.cache, key) else {
+            return f();
+        };
+
+        if let Some(hash) = cache.get(&key) {
+            return Ok(hash);
+        }
+
+        let hash = f()?;
+        // failing to write to the cache should not fail the prefetch
+        let _ = cache.insert(key, hash.clone());
+        Ok(hash)
+    }
+
     pub fn has_args(&self) -> bool {
         !(self.args.is_empty() && self.args_str.is_empty())
     }
@@ -38,76 +116,118 @@ impl FetcherConfig {
         }
     }
 
-    // assumes overwrites have been merged
-    pub fn write_nix_args(&mut self, out: &mut impl Write, indent: &str) -> Result<()> {
+    /// Add the arguments and the overwrites, which replace the arguments with the same names
+    /// and `rev_key` for the overwritten revision
+    pub fn extend_output(&self, output: &mut FetcherOutput, rev_key: &str) {
         for (key, value) in &self.args {
-            if let Some(value) = self.overwrites.remove(key) {
-                writeln!(out, "{indent}  {key} = {value};")?;
-            } else {
-                writeln!(out, "{indent}  {key} = {value};")?;
-            }
+            output.push(key, Arg::Nix(value.clone()));
         }
-
         for (key, value) in &self.args_str {
-            if let Some(value) = self.overwrites.remove(key) {
-                writeln!(out, "{indent}  {key} = {value};")?;
-            } else {
-                writeln!(out, r#"{indent}  {key} = "{value}";"#)?;
-            }
+            output.push_str(key, value);
         }
 
         for (key, value) in &self.overwrites {
-            writeln!(out, "{indent}  {key} = {value};")?;
-        }
-
-        Ok(())
-    }
-
-    pub fn extend_fetcher_args(&self, fetcher_args: &mut Value, rev_key: &str) {
-        for (key, value) in &self.args {
-            fetcher_args[key] = json!({
-                "type": "nix",
-                "value": value,
-            });
-        }
-        for (key, value) in &self.args_str {
-            fetcher_args[key] = json!(value);
-        }
-
-        for (key, value) in &self.overwrites {
-            fetcher_args[key] = json!({
-                "type": "nix",
-                "value": value,
-            });
+            output.set(key, Arg::Nix(value.clone()));
         }
         for (key, value) in &self.overwrites_str {
-            fetcher_args[key] = json!(value);
+            output.set(key, Arg::String(value.clone()));
         }
         if let Some(rev) = &self.overwrite_rev {
-            fetcher_args[rev_key] = json!({
-                "type": "nix",
-                "value": rev,
-            });
+            output.set(rev_key, Arg::Nix(rev.clone()));
         }
         if let Some(rev) = &self.overwrite_rev_str {
-            fetcher_args[rev_key] = json!(rev);
+            output.set(rev_key, Arg::String(rev.clone()));
         }
     }
 }
 
-impl From<Opts> for FetcherConfig {
-    fn from(opts: Opts) -> Self {
+impl Default for FetcherConfig {
+    fn default() -> Self {
         Self {
+            rev: None,
+            submodules: None,
+            private: false,
+            latest: Latest::Commit,
+            prerelease: false,
+            tag_pattern: None,
+            nixpkgs: "<nixpkgs>".into(),
+            prefetcher: Arc::new(Nix),
+            cache: None,
+            indent: 0,
+            jobs: default_jobs(),
+            args: FxHashMap::default(),
+            args_str: FxHashMap::default(),
+            overwrites: FxHashMap::default(),
+            overwrites_str: FxHashMap::default(),
+            overwrite_rev: None,
+            overwrite_rev_str: None,
+        }
+    }
+}
+
+impl TryFrom<Opts> for FetcherConfig {
+    type Error = eyre::Report;
+
+    fn try_from(opts: Opts) -> Result<Self> {
+        let backend: Box<dyn Prefetcher> = match opts.backend {
+            Backend::Nix => Box::new(Nix),
+            Backend::Native => Box::new(Native {
+                jobs: opts.jobs.unwrap_or_else(default_jobs),
+            }),
+            Backend::DryRun => Box::new(DryRun),
+        };
+        // recorded and fake hashes should never be cached, and cached hashes should be recorded
+        let cache = if opts.no_cache
+            || opts.record.is_some()
+            || opts.replay.is_some()
+            || matches!(opts.backend, Backend::DryRun)
+        {
+            None
+        } else {
+            Cache::new(opts.refresh)
+        };
+
+        let prefetcher: Arc<dyn Prefetcher> = if let Some(path) = opts.replay {
+            Arc::new(Fixtures::replay(path)?)
+        } else if let Some(path) = opts.record {
+            Arc::new(Fixtures::record(path, backend)?)
+        } else {
+            backend.into()
+        };
+
+        Ok(Self {
             rev: opts.rev,
             submodules: opts.submodules,
+            private: opts.private,
+            latest: if opts.latest_release {
+                Latest::Release
+            } else if opts.latest_tag {
+                Latest::Tag
+            } else {
+                Latest::Commit
+            },
+            prerelease: opts.prerelease,
+            tag_pattern: opts
+                .tag_pattern
+                .as_deref()
+                .map(Regex::new)
+                .transpose()
+                .wrap_err("invalid --tag-pattern")?,
             nixpkgs: opts.nixpkgs,
+            prefetcher,
+            cache,
             indent: opts.indent,
+            jobs: opts.jobs.unwrap_or_else(default_jobs),
             args: opts.args.into_iter().tuples().collect(),
             args_str: opts.args_str.into_iter().tuples().collect(),
             overwrites: opts.overwrites.into_iter().tuples().collect(),
             overwrites_str: opts.overwrites_str.into_iter().tuples().collect(),
             overwrite_rev: opts.overwrite_rev,
             overwrite_rev_str: opts.overwrite_rev_str,
-        }
+        })
     }
 }
+
+fn default_jobs() -> NonZeroUsize {
+    thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)
+}
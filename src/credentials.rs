@@ -0,0 +1,337 @@
+use std::{
+    env, fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::{LazyLock, Mutex, OnceLock},
+};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use rustc_hash::FxHashMap;
+
+/// Forges with their own environment variables for tokens
+#[derive(Clone, Copy)]
+pub enum Forge {
+    GitHub,
+    GitLab,
+    Gitea,
+}
+
+/// A token, or a password with the username it belongs to
+#[derive(Clone)]
+pub struct Credential {
+    pub username: Option<String>,
+    pub secret: String,
+}
+
+impl Credential {
+    /// The value of the `Authorization` header for HTTP basic authentication,
+    /// which forges accept with any username for tokens
+    pub fn basic(&self) -> String {
+        let username = self.username.as_deref().unwrap_or("x-access-token");
+        format!(
+            "Basic {}",
+            BASE64_STANDARD.encode(format!("{username}:{}", self.secret)),
+        )
+    }
+}
+
+static CONFIGURED: OnceLock<FxHashMap<String, String>> = OnceLock::new();
+
+// looking up netrc and git credential helpers can be slow, so they are only done once per host
+static FOUND: LazyLock<Mutex<FxHashMap<String, Option<Credential>>>> =
+    LazyLock::new(Mutex::default);
+
+/// Use tokens from the config file, keyed by host
+pub fn configure(tokens: FxHashMap<String, String>) {
+    let _ = CONFIGURED.set(tokens);
+}
+
+/// Credentials for `host`, from the first of these that has them
+///
+/// - `NURL_TOKEN_<HOST>`, where `<HOST>` is uppercased with other characters replaced by `_`
+/// - environment variables of the forge, like `GH_TOKEN` and `GITLAB_TOKEN`
+/// - the `tokens` table of the config file
+/// - `$NETRC` or `~/.netrc`
+/// - git credential helpers
+pub fn get(host: &str, forge: Option<Forge>) -> Option<Credential> {
+    if let Some(credential) = configured(host, forge) {
+        return Some(credential);
+    }
+
+    let mut found = FOUND.lock().ok()?;
+    found
+        .entry(host.into())
+        .or_insert_with(|| netrc(host, true).or_else(|| credential_helper(host)))
+        .clone()
+}
+
+// credentials that were set for `host` specifically, or for `forge`
+fn configured(host: &str, forge: Option<Forge>) -> Option<Credential> {
+    let token = |secret| Credential {
+        username: None,
+        secret,
+    };
+
+    let key = host
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>();
+    if let Some(secret) = var(&format!("NURL_TOKEN_{key}")) {
+        return Some(token(secret));
+    }
+
+    if let Some(secret) = env_vars(host, forge).iter().find_map(|key| var(key)) {
+        return Some(token(secret));
+    }
+
+    CONFIGURED
+        .get()
+        .and_then(|tokens| tokens.get(host))
+        .map(|secret| token(secret.clone()))
+}
+
+fn var(key: &str) -> Option<String> {
+    env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+// the same variables as the command line tools of the forges
+fn env_vars(host: &str, forge: Option<Forge>) -> &'static [&'static str] {
+    match (forge, host) {
+        (_, "github.com") => &["GH_TOKEN", "GITHUB_TOKEN", "GITHUB_API_TOKEN"],
+        (Some(Forge::GitHub), _) => &["GH_ENTERPRISE_TOKEN", "GITHUB_ENTERPRISE_TOKEN"],
+        (Some(Forge::GitLab), _) | (_, "gitlab.com") => &["GITLAB_TOKEN", "GL_TOKEN"],
+        (Some(Forge::Gitea), _) | (_, "codeberg.org") => &["GITEA_TOKEN", "FORGEJO_TOKEN"],
+        _ => &[],
+    }
+}
+
+fn netrc(host: &str, default: bool) -> Option<Credential> {
+    let path = match env::var_os("NETRC") {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".netrc"),
+    };
+    parse_netrc(&fs::read_to_string(path).ok()?, host, default)
+}
+
+// the first entry for the host takes precedence over the default entry, if `default` is true
+fn parse_netrc(contents: &str, host: &str, default: bool) -> Option<Credential> {
+    let mut entries = Vec::new();
+    let mut words = contents.split_whitespace();
+
+    while let Some(word) = words.next() {
+        match word {
+            "machine" => entries.push((words.next(), None, None)),
+            "default" => entries.push((None, None, None)),
+            "login" => {
+                if let Some((_, login, _)) = entries.last_mut() {
+                    *login = words.next();
+                }
+            }
+            "password" => {
+                if let Some((_, _, password)) = entries.last_mut() {
+                    *password = words.next();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (_, login, password) = entries
+        .iter()
+        .find(|(machine, ..)| *machine == Some(host))
+        .or_else(|| {
+            entries
+                .iter()
+                .find(|(machine, ..)| default && machine.is_none())
+        })?;
+
+    Some(Credential {
+        username: login.map(Into::into),
+        secret: (*password)?.into(),
+    })
+}
+
+// https://git-scm.com/docs/git-credential#IOFMT
+fn credential_helper(host: &str) -> Option<Credential> {
+    let mut child = Command::new("git")
+        .args(["credential", "fill"])
+        // never prompt when no helper has the credentials
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .env("GIT_ASKPASS", "")
+        .env("SSH_ASKPASS", "")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+
+    child
+        .stdin
+        .take()?
+        .write_all(format!("protocol=https\nhost={host}\n\n").as_bytes())
+        .ok()?;
+
+    let output = child.wait_with_output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let output = String::from_utf8(output.stdout).ok()?;
+    let mut username = None;
+    let mut password = None;
+    for line in output.lines() {
+        match line.split_once('=') {
+            Some(("username", value)) => username = Some(value.into()),
+            Some(("password", value)) => password = Some(value.into()),
+            _ => {}
+        }
+    }
+
+    Some(Credential {
+        username,
+        secret: password?,
+    })
+}
+
+/// The host of `url`, ignoring the scheme, user info, and port
+pub fn host(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    Some(host.split(':').next().unwrap_or(host))
+}
+
+// credentials are never sent over plain HTTP
+fn https_host(url: &str) -> Option<&str> {
+    let url = url.strip_prefix("git+").unwrap_or(url);
+    url.starts_with("https://").then(|| host(url))?
+}
+
+/// The `Authorization` header for git over HTTPS to `url`
+pub fn authorization(url: &str) -> Option<String> {
+    Some(get(https_host(url)?, None)?.basic())
+}
+
+/// The `Authorization` header for downloads from `url`, which can be on any host,
+/// so only credentials for the host itself are used, not the default netrc entry
+/// or git credential helpers
+pub fn download_authorization(url: &str) -> Option<String> {
+    let host = https_host(url)?;
+    Some(
+        configured(host, None)
+            .or_else(|| netrc(host, false))?
+            .basic(),
+    )
+}
+
+/// Environment variables that make git send credentials for `url` without a credential helper
+/// or putting them in the command line, added after the configuration from the environment
+pub fn git_env(url: &str) -> Vec<(String, String)> {
+    let (Some(host), Some(authorization)) = (https_host(url), authorization(url)) else {
+        return Vec::new();
+    };
+    let i = var("GIT_CONFIG_COUNT")
+        .and_then(|count| count.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    vec![
+        ("GIT_CONFIG_COUNT".into(), (i + 1).to_string()),
+        (
+            format!("GIT_CONFIG_KEY_{i}"),
+            format!("http.https://{host}/.extraHeader"),
+        ),
+        (
+            format!("GIT_CONFIG_VALUE_{i}"),
+            format!("Authorization: {authorization}"),
+        ),
+    ]
+}
+
+/// `NIX_CONFIG` with an access token for the host of a `github:` or `gitlab:` flake reference
+/// added, keeping the existing configuration
+pub fn nix_config(flake_ref: &str) -> Option<String> {
+    let (forge, rest, default_host) = if let Some(rest) = flake_ref.strip_prefix("github:") {
+        (Forge::GitHub, rest, "github.com")
+    } else if let Some(rest) = flake_ref.strip_prefix("gitlab:") {
+        (Forge::GitLab, rest, "gitlab.com")
+    } else {
+        return None;
+    };
+
+    let host = rest
+        .split_once("?host=")
+        .or_else(|| rest.split_once("&host="))
+        .map_or(default_host, |(_, host)| {
+            host.split('&').next().unwrap_or(host)
+        });
+    let secret = get(host, Some(forge))?.secret;
+
+    let mut config = env::var("NIX_CONFIG").unwrap_or_default();
+    if !config.is_empty() {
+        config.push('\n');
+    }
+    match forge {
+        Forge::GitLab => config.push_str(&format!("extra-access-tokens = {host}=PAT:{secret}")),
+        _ => config.push_str(&format!("extra-access-tokens = {host}={secret}")),
+    }
+    Some(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{host, parse_netrc};
+
+    #[test]
+    fn hosts() {
+        for (url, expected) in [
+            ("https://github.com/nix-community/nurl", Some("github.com")),
+            (
+                "git+https://user@github.com:443/a/b.git",
+                Some("github.com"),
+            ),
+            ("http://127.0.0.1:8080/repo.git?ref=main", Some("127.0.0.1")),
+            ("github:nix-community/nurl", None),
+        ] {
+            assert_eq!(
+                host(url.strip_prefix("git+").unwrap_or(url)),
+                expected,
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    fn netrc() {
+        let contents = "
+            machine gitlab.example.org login alice password glpat-1
+            default
+                login anonymous
+                password guest
+            machine github.com password ghp-2
+        ";
+
+        let credential = parse_netrc(contents, "gitlab.example.org", true).unwrap();
+        assert_eq!(credential.username.as_deref(), Some("alice"));
+        assert_eq!(credential.secret, "glpat-1");
+
+        let credential = parse_netrc(contents, "github.com", true).unwrap();
+        assert_eq!(credential.username, None);
+        assert_eq!(credential.secret, "ghp-2");
+
+        let credential = parse_netrc(contents, "example.org", true).unwrap();
+        assert_eq!(credential.username.as_deref(), Some("anonymous"));
+        assert_eq!(credential.secret, "guest");
+
+        assert!(parse_netrc(contents, "example.org", false).is_none());
+        assert!(parse_netrc("machine example.org login bob", "example.org", true).is_none());
+    }
+}
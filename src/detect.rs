@@ -0,0 +1,251 @@
+use std::{
+    fs,
+    path::Path,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+use rustc_hash::FxHashMap;
+use serde_json::Value;
+use ureq::{Agent, http::StatusCode};
+
+use crate::{cache::cache_dir, cli::FetcherFunction, credentials};
+
+static DETECTED: LazyLock<Mutex<FxHashMap<String, Option<FetcherFunction>>>> =
+    LazyLock::new(Mutex::default);
+
+/// Guess the forge running on `host` by probing the APIs of Gitea/Forgejo, GitLab,
+/// and GitHub Enterprise Server
+///
+/// Forges that were found are cached per host in `$XDG_CACHE_HOME/nurl/forges.json`,
+/// hosts without a known forge are only remembered until nurl exits, since they might be
+/// unavailable, or need credentials that are added later
+pub fn detect_forge(host: &str) -> Option<FetcherFunction> {
+    {
+        let mut detected = DETECTED.lock().ok()?;
+        if detected.is_empty() {
+            *detected = cache_dir().map_or_else(FxHashMap::default, |dir| read_cache(&dir));
+        }
+        if let Some(fetcher) = detected.get(host) {
+            return *fetcher;
+        }
+    }
+
+    // probes can take seconds, so the lock is not held to not block detecting other hosts
+    let fetcher = probe(&format!("https://{host}")).ok()?;
+
+    let mut detected = DETECTED.lock().ok()?;
+    detected.insert(host.into(), fetcher);
+    if let Some(dir) = cache_dir() {
+        write_cache(&dir, &detected);
+    }
+
+    fetcher
+}
+
+// `base` is the scheme and the host of the URLs of the APIs
+fn probe(base: &str) -> Result<Option<FetcherFunction>, ureq::Error> {
+    let agent: Agent = Agent::config_builder()
+        .http_status_as_error(false)
+        .timeout_global(Some(Duration::from_secs(10)))
+        .build()
+        .into();
+
+    let get = |path| -> Result<_, ureq::Error> {
+        let url = format!("{base}{path}");
+        let mut req = agent.get(&url);
+        if let Some(authorization) = credentials::authorization(&url) {
+            req = req.header("Authorization", &authorization);
+        }
+        let mut resp = req.call()?;
+        let status = resp.status();
+        Ok((
+            status,
+            resp.body_mut().read_json::<Value>().unwrap_or_default(),
+        ))
+    };
+
+    // Forgejo is compatible with fetchFromGitea
+    if let (StatusCode::OK, json) = get("/api/v1/version")?
+        && json["version"].is_string()
+    {
+        return Ok(Some(FetcherFunction::FetchFromGitea));
+    }
+
+    // the version endpoint requires authentication on most instances
+    match get("/api/v4/version")? {
+        (StatusCode::OK, json) if json["revision"].is_string() => {
+            return Ok(Some(FetcherFunction::FetchFromGitLab));
+        }
+        (StatusCode::UNAUTHORIZED, json) if json["message"] == "401 Unauthorized" => {
+            return Ok(Some(FetcherFunction::FetchFromGitLab));
+        }
+        _ => {}
+    }
+
+    if let (StatusCode::OK, json) = get("/api/v3/meta")?
+        && json["installed_version"].is_string()
+    {
+        return Ok(Some(FetcherFunction::FetchFromGitHub));
+    }
+
+    Ok(None)
+}
+
+// hosts without a forge were cached as `null` by earlier versions, and are probed again
+fn read_cache(dir: &Path) -> FxHashMap<String, Option<FetcherFunction>> {
+    let Ok(contents) = fs::read(dir.join("forges.json")) else {
+        return FxHashMap::default();
+    };
+    let Ok(forges) = serde_json::from_slice::<FxHashMap<String, Option<String>>>(&contents) else {
+        return FxHashMap::default();
+    };
+
+    forges
+        .into_iter()
+        .filter_map(|(host, fetcher)| {
+            Some((
+                host,
+                Some(FetcherFunction::from_str(&fetcher?, false).ok()?),
+            ))
+        })
+        .collect()
+}
+
+// the cache is best effort, failing to write it is not an error
+fn write_cache(dir: &Path, detected: &FxHashMap<String, Option<FetcherFunction>>) {
+    let forges: FxHashMap<_, _> = detected
+        .iter()
+        .filter_map(|(host, fetcher)| {
+            Some((
+                host,
+                fetcher.as_ref()?.to_possible_value()?.get_name().to_owned(),
+            ))
+        })
+        .collect();
+
+    if let Ok(contents) = serde_json::to_vec_pretty(&forges) {
+        let _ = fs::create_dir_all(dir).and_then(|_| fs::write(dir.join("forges.json"), contents));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    use rustc_hash::FxHashMap;
+    use tempfile::TempDir;
+
+    use super::{probe, read_cache, write_cache};
+    use crate::cli::FetcherFunction;
+
+    // respond to the paths in `routes` with their status and body, and with 404 otherwise
+    fn serve(routes: &'static [(&str, u16, &str)]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let path = line.split(' ').nth(1).unwrap().to_owned();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                }
+
+                let (status, body) = routes
+                    .iter()
+                    .find_map(|(route, status, body)| (*route == path).then_some((*status, *body)))
+                    .unwrap_or((404, "{}"));
+                write!(
+                    stream,
+                    "HTTP/1.1 {status} Status\r\nContent-Type: application/json\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len(),
+                )
+                .unwrap();
+            }
+        });
+
+        format!("http://{addr}")
+    }
+
+    #[test]
+    fn forges() {
+        for (routes, expected) in [
+            (
+                &[(
+                    "/api/v1/version",
+                    200,
+                    r#"{"version":"11.0.1+gitea-1.22.0"}"#,
+                )][..],
+                Some(FetcherFunction::FetchFromGitea),
+            ),
+            (
+                &[("/api/v4/version", 401, r#"{"message":"401 Unauthorized"}"#)],
+                Some(FetcherFunction::FetchFromGitLab),
+            ),
+            (
+                &[(
+                    "/api/v4/version",
+                    200,
+                    r#"{"version":"17.0.0","revision":"abc"}"#,
+                )],
+                Some(FetcherFunction::FetchFromGitLab),
+            ),
+            (
+                &[("/api/v3/meta", 200, r#"{"installed_version":"3.13.0"}"#)],
+                Some(FetcherFunction::FetchFromGitHub),
+            ),
+            (&[("/api/v1/version", 200, "not json")], None),
+        ] {
+            assert_eq!(
+                format!("{:?}", probe(&serve(routes)).unwrap()),
+                format!("{expected:?}"),
+                "{routes:?}",
+            );
+        }
+    }
+
+    #[test]
+    fn unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        assert!(probe(&format!("http://{addr}")).is_err());
+    }
+
+    #[test]
+    fn cache() {
+        let dir = TempDir::new().unwrap();
+        let detected = FxHashMap::from_iter([
+            (
+                "git.example.org".to_owned(),
+                Some(FetcherFunction::FetchFromGitLab),
+            ),
+            ("down.example.org".to_owned(), None),
+        ]);
+        write_cache(dir.path(), &detected);
+
+        // hosts without a forge are probed again
+        let cached = read_cache(dir.path());
+        assert_eq!(cached.len(), 1);
+        assert!(matches!(
+            cached["git.example.org"],
+            Some(FetcherFunction::FetchFromGitLab),
+        ));
+    }
+}
@@ -10,6 +10,10 @@ impl SimpleFetcher<'_, 2> for FetchFromBitbucket {
     const KEYS: [&'static str; 2] = ["owner", "repo"];
     const NAME: &'static str = "fetchFromBitbucket";
     const REV_KEY: RevKey = RevKey::RevOrTag;
+
+    fn git_url(&self, [owner, repo]: &[&str; 2]) -> Option<String> {
+        Some(format!("https://bitbucket.org/{owner}/{repo}.git"))
+    }
 }
 
 impl SimpleUrlFetcher<'_, 2> for FetchFromBitbucket {
@@ -3,45 +3,65 @@ use std::io::Write;
 use eyre::{OptionExt, Result, bail};
 use serde_json::json;
 
-use crate::{Url, config::FetcherConfig, fetcher::Fetcher};
+use crate::{
+    Url,
+    config::FetcherConfig,
+    fetcher::Fetcher,
+    ls_refs::{self, RefKind},
+    output::{Arg, FetcherOutput},
+    rev::{self, RevKind, is_commit_hash},
+};
 
 pub struct BuiltinsFetchGit;
 
-impl<'a> Fetcher<'a> for BuiltinsFetchGit {
-    fn fetch_nix(&self, out: &mut impl Write, url: &'a Url, mut cfg: FetcherConfig) -> Result<()> {
-        let indent = " ".repeat(cfg.indent);
-        let rev = cfg
-            .rev
-            .as_ref()
-            .ok_or_eyre("builtins.fetchGit does not support feching the latest revision")?;
-        let rev_key = if rev.len() == 40 { "rev" } else { "ref" };
-
-        writeln!(out, "builtins.fetchGit {{")?;
-
-        if let Some(url) = cfg.overwrites.remove("url") {
-            writeln!(out, "{indent}  {url} = {url};")?;
-        } else {
-            writeln!(out, r#"{indent}  url = "{url}";"#)?;
-        }
-
-        if let Some(rev_key) = &cfg.overwrite_rev {
-            writeln!(out, "{indent}  {rev_key} = {rev};")?;
-        } else if let Some(rev) = cfg.overwrites.remove(rev_key) {
-            writeln!(out, "{indent}  {rev_key} = {rev};")?;
-        } else {
-            writeln!(out, r#"{indent}  {rev_key} = "{rev}";"#)?;
-        }
+struct Revision {
+    rev_key: &'static str,
+    rev: String,
+    /// The branch a commit was pinned from, which is needed to fetch commits not on `HEAD`
+    git_ref: Option<String>,
+}
 
-        if let Some(submodules) = cfg.overwrites.remove("submodules") {
-            writeln!(out, "{indent}  submodules = {submodules};")?;
-        } else if matches!(cfg.submodules, Some(true)) {
-            writeln!(out, "{indent}  submodules = true;")?;
-        }
+// the commit HEAD points to if the revision is not specified
+fn resolve_rev(url: &Url, cfg: &FetcherConfig) -> Result<Revision> {
+    let revision = |rev_key, rev: &str| Revision {
+        rev_key,
+        rev: rev.into(),
+        git_ref: None,
+    };
+
+    let Some(rev) = &cfg.rev else {
+        return Ok(revision("rev", &cfg.head(url.as_str())?));
+    };
+    let kind = rev::classify(rev);
+    if kind == RevKind::Commit {
+        return Ok(revision("rev", rev));
+    }
+    if rev.starts_with("refs/tags/") {
+        return Ok(revision("ref", rev));
+    }
 
-        cfg.write_nix_args(out, &indent)?;
-        write!(out, "{indent}}}")?;
+    Ok(match cfg.resolve_ref(url.as_str(), rev)? {
+        // builtins.fetchGit assumes refs without a prefix are branches
+        RefKind::Tag => revision("ref", &format!("refs/tags/{rev}")),
+        RefKind::Branch(commit) => Revision {
+            rev_key: "rev",
+            rev: commit,
+            git_ref: Some(rev.clone()),
+        },
+        RefKind::Unknown => match kind {
+            RevKind::ShortCommit => match ls_refs::expand_commit(url.as_str(), rev)? {
+                Some(commit) => revision("rev", &commit),
+                None => revision("ref", rev),
+            },
+            _ => revision("ref", rev),
+        },
+    })
+}
 
-        Ok(())
+impl<'a> Fetcher<'a> for BuiltinsFetchGit {
+    fn fetch_nix(&self, out: &mut impl Write, url: &'a Url, cfg: FetcherConfig) -> Result<()> {
+        let indent = " ".repeat(cfg.indent);
+        self.fetch_output(url, cfg)?.write_nix(out, &indent)
     }
 
     fn fetch_hash(&self, _: &mut impl Write, _: &'a Url, _: FetcherConfig) -> Result<()> {
@@ -49,38 +69,14 @@ impl<'a> Fetcher<'a> for BuiltinsFetchGit {
     }
 
     fn fetch_json(&self, out: &mut impl Write, url: &'a Url, cfg: FetcherConfig) -> Result<()> {
-        let rev = cfg
-            .rev
-            .as_ref()
-            .ok_or_eyre("builtins.fetchGit does not support feching the latest revision")?;
-        let rev_key = if rev.len() == 40 { "rev" } else { "ref" };
-
-        let mut fetcher_args = json!({
-            "url": url.as_str(),
-            rev_key: rev,
-        });
-
-        if matches!(cfg.submodules, Some(true)) {
-            fetcher_args["submodules"] = json!(true);
-        }
-
-        cfg.extend_fetcher_args(&mut fetcher_args, rev_key);
-
-        serde_json::to_writer(
-            out,
-            &json!({
-                "fetcher": "builtins.fetchGit",
-                "args": fetcher_args,
-            }),
-        )?;
-
+        serde_json::to_writer(out, &self.fetch_output(url, cfg)?.to_json()?)?;
         Ok(())
     }
 
     fn to_json(&'a self, out: &mut impl Write, url: &'a Url, rev: Option<String>) -> Result<()> {
         let rev =
             rev.ok_or_eyre("builtins.fetchGit does not support feching the latest revision")?;
-        let rev_key = if rev.len() == 40 { "rev" } else { "ref" };
+        let rev_key = if is_commit_hash(&rev) { "rev" } else { "ref" };
 
         serde_json::to_writer(
             out,
@@ -95,4 +91,59 @@ impl<'a> Fetcher<'a> for BuiltinsFetchGit {
 
         Ok(())
     }
+
+    fn fetch_output(&self, url: &'a Url, cfg: FetcherConfig) -> Result<FetcherOutput> {
+        let Revision {
+            rev_key,
+            rev,
+            git_ref,
+        } = resolve_rev(url, &cfg)?;
+
+        let mut output = FetcherOutput::new("builtins.fetchGit");
+        output.push_str("url", url.as_str());
+        if let Some(git_ref) = &git_ref {
+            output.push_str("ref", git_ref);
+        }
+        output.push_str(rev_key, rev);
+
+        if matches!(cfg.submodules, Some(true)) {
+            output.push("submodules", Arg::Bool(true));
+        }
+
+        cfg.extend_output(&mut output, rev_key);
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use tempfile::TempDir;
+
+    use super::BuiltinsFetchGit;
+    use crate::{Url, config::FetcherConfig, fetcher::Fetcher, prefetch::Fixtures};
+
+    #[test]
+    fn head() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("fixtures.json");
+        fs::write(
+            &path,
+            r#"{ "head https://example.org/r.git": "4d5515e112addf88b4107dd9357243dc10624e58" }"#,
+        )
+        .unwrap();
+        let cfg = FetcherConfig {
+            prefetcher: Arc::new(Fixtures::replay(path).unwrap()),
+            ..Default::default()
+        };
+
+        let url = Url::new("https://example.org/r.git", "r.git");
+        let output = BuiltinsFetchGit.fetch_output(&url, cfg).unwrap();
+        assert_eq!(
+            serde_json::to_string(&output).unwrap(),
+            r#"{"args":{"rev":"4d5515e112addf88b4107dd9357243dc10624e58","url":"https://example.org/r.git"},"fetcher":"builtins.fetchGit"}"#,
+        );
+    }
 }
@@ -1,3 +1,6 @@
+use eyre::Result;
+use serde::Deserialize;
+
 use crate::{
     Url, impl_fetcher,
     simple::{RevKey, SimpleFetcher, SimpleUrlFetcher},
@@ -6,6 +9,12 @@ use crate::{
 pub struct FetchCrate(pub bool);
 impl_fetcher!(FetchCrate);
 
+#[derive(Deserialize)]
+struct IndexEntry {
+    vers: String,
+    yanked: bool,
+}
+
 impl SimpleFetcher<'_, 1> for FetchCrate {
     const KEYS: [&'static str; 1] = ["pname"];
     const NAME: &'static str = "fetchCrate";
@@ -22,6 +31,32 @@ impl SimpleFetcher<'_, 1> for FetchCrate {
             }
         }])
     }
+
+    // https://doc.rust-lang.org/cargo/reference/registry-index.html#index-files
+    fn fetch_versions(&self, [pname]: &[&str; 1]) -> Result<Vec<String>> {
+        let name = pname.to_ascii_lowercase();
+        let path = match name.len() {
+            1 => format!("1/{name}"),
+            2 => format!("2/{name}"),
+            3 => format!("3/{}/{name}", &name[.. 1]),
+            _ => format!("{}/{}/{name}", &name[.. 2], &name[2 .. 4]),
+        };
+
+        let index = ureq::get(&format!("https://index.crates.io/{path}"))
+            .call()?
+            .into_body()
+            .read_to_string()?;
+
+        let mut versions = Vec::new();
+        for line in index.lines() {
+            let entry: IndexEntry = serde_json::from_str(line)?;
+            if !entry.yanked {
+                versions.push(entry.vers);
+            }
+        }
+
+        Ok(versions)
+    }
 }
 
 impl SimpleUrlFetcher<'_, 1> for FetchCrate {
@@ -4,7 +4,7 @@ use crate::{
     GitScheme, Url,
     config::FetcherConfig,
     impl_fetcher,
-    prefetch::git_prefetch,
+    prefetch::Source,
     simple::{RevKey, SimpleFetcher},
 };
 
@@ -25,6 +25,10 @@ impl<'a> SimpleFetcher<'a, 1> for Fetchgit {
             url.as_str()
         }])
     }
+
+    fn git_url(&self, [url]: &[&str; 1]) -> Option<String> {
+        Some((*url).into())
+    }
 }
 
 impl Fetchgit {
@@ -39,7 +43,49 @@ impl Fetchgit {
         if cfg.has_args() {
             self.fetch_fod(values, rev_key, rev, submodules, cfg)
         } else {
-            git_prefetch(matches!(self.0, GitScheme::Yes), url, rev, !submodules)
+            cfg.prefetch(Source::git(url, rev_key, rev, !submodules))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use tempfile::TempDir;
+
+    use super::Fetchgit;
+    use crate::{GitScheme, config::FetcherConfig, simple::SimpleFetcher};
+
+    // refs of repositories not served over HTTP are listed with git ls-remote
+    #[test]
+    fn local_branch() {
+        let dir = TempDir::new().unwrap();
+        let git = |args: &[&str]| {
+            let output = Command::new("git")
+                .args(args)
+                .current_dir(dir.path())
+                .env("GIT_CONFIG_GLOBAL", "/dev/null")
+                .env("GIT_AUTHOR_NAME", "nurl")
+                .env("GIT_AUTHOR_EMAIL", "nurl@example.org")
+                .env("GIT_COMMITTER_NAME", "nurl")
+                .env("GIT_COMMITTER_EMAIL", "nurl@example.org")
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "git {args:?}");
+            String::from_utf8(output.stdout).unwrap().trim().to_owned()
+        };
+        git(&["init", "-q", "-b", "main"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "first"]);
+        let commit = git(&["rev-parse", "HEAD"]);
+
+        let url = format!("file://{}", dir.path().display());
+        let cfg = FetcherConfig {
+            rev: Some("main".into()),
+            ..Default::default()
+        };
+        let revision = Fetchgit(GitScheme::No).resolve_rev(&[&url], &cfg).unwrap();
+        assert_eq!(revision.rev, commit);
+        assert_eq!(revision.branch.as_deref(), Some("main"));
+    }
+}
@@ -1,11 +1,14 @@
 use eyre::{Result, WrapErr};
 use serde::Deserialize;
+use ureq::{RequestBuilder, typestate::WithoutBody};
 
 use crate::{
     config::FetcherConfig,
+    credentials::{self, Forge},
+    fetcher::patch::CommitRange,
     impl_fetcher,
-    prefetch::{flake_prefetch, git_prefetch},
-    simple::{RevKey, SimpleFetcher},
+    prefetch::Source,
+    simple::{RevKey, SimpleFetcher, Tag, paginate},
 };
 
 pub struct FetchFromGitea<'a>(pub &'a str);
@@ -16,6 +19,24 @@ struct Commit {
     sha: String,
 }
 
+#[derive(Deserialize)]
+struct PullRequest {
+    base: Commit,
+    head: Commit,
+}
+
+#[derive(Deserialize)]
+struct GitTag {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    draft: bool,
+    prerelease: bool,
+}
+
 impl SimpleFetcher<'_, 2> for FetchFromGitea<'_> {
     const KEYS: [&'static str; 2] = ["owner", "repo"];
     const NAME: &'static str = "fetchFromGitea";
@@ -26,13 +47,18 @@ impl SimpleFetcher<'_, 2> for FetchFromGitea<'_> {
         Some(self.0)
     }
 
+    fn git_url(&self, [owner, repo]: &[&str; 2]) -> Option<String> {
+        Some(format!("https://{}/{owner}/{repo}", self.0))
+    }
+
     fn fetch_rev(&self, [owner, repo]: &[&str; 2]) -> Result<String> {
         let url = format!(
             "https://{}/api/v1/repos/{owner}/{repo}/commits?limit=1&stat=false",
             self.0,
         );
 
-        let [Commit { sha }] = ureq::get(&url)
+        let [Commit { sha }] = self
+            .request(&url)
             .call()?
             .into_body()
             .read_json::<[_; 1]>()
@@ -40,9 +66,92 @@ impl SimpleFetcher<'_, 2> for FetchFromGitea<'_> {
 
         Ok(sha)
     }
+
+    fn expand_commit(&self, [owner, repo]: &[&str; 2], short: &str) -> Result<Option<String>> {
+        let url = format!(
+            "https://{}/api/v1/repos/{owner}/{repo}/git/commits/{short}?stat=false",
+            self.0,
+        );
+
+        match self.request(&url).call() {
+            Ok(resp) => Ok(Some(resp.into_body().read_json::<Commit>()?.sha)),
+            Err(ureq::Error::StatusCode(404 | 422)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn fetch_tags(
+        &self,
+        [owner, repo]: &[&str; 2],
+        releases: bool,
+        cfg: &FetcherConfig,
+    ) -> Result<Vec<Tag>> {
+        let url = format!("https://{}/api/v1/repos/{owner}/{repo}", self.0);
+
+        Ok(if releases {
+            paginate(50, cfg.jobs, |page| {
+                Ok(self
+                    .request(&format!("{url}/releases?draft=false&limit=50&page={page}"))
+                    .call()?
+                    .into_body()
+                    .read_json::<Vec<Release>>()?)
+            })?
+            .into_iter()
+            .filter(|release| !release.draft)
+            .map(|release| Tag {
+                name: release.tag_name,
+                prerelease: release.prerelease,
+            })
+            .collect()
+        } else {
+            paginate(50, cfg.jobs, |page| {
+                Ok(self
+                    .request(&format!("{url}/tags?limit=50&page={page}"))
+                    .call()?
+                    .into_body()
+                    .read_json::<Vec<GitTag>>()?)
+            })?
+            .into_iter()
+            .map(|tag| Tag {
+                name: tag.name,
+                prerelease: false,
+            })
+            .collect()
+        })
+    }
 }
 
 impl FetchFromGitea<'_> {
+    // https://docs.gitea.com/development/api-usage#authentication
+    fn request(&self, url: &str) -> RequestBuilder<WithoutBody> {
+        let request = ureq::get(url);
+        match credentials::get(self.0, Some(Forge::Gitea)) {
+            Some(credential) => {
+                request.header("Authorization", &format!("token {}", credential.secret))
+            }
+            None => request,
+        }
+    }
+
+    /// The commits of pull request `number`
+    pub fn pull_request(&self, owner: &str, repo: &str, number: &str) -> Result<CommitRange> {
+        let url = format!(
+            "https://{}/api/v1/repos/{owner}/{repo}/pulls/{number}",
+            self.0,
+        );
+        let pr = self
+            .request(&url)
+            .call()
+            .wrap_err_with(|| format!("failed to request {url}"))?
+            .into_body()
+            .read_json::<PullRequest>()?;
+
+        Ok(CommitRange {
+            base: pr.base.sha,
+            head: pr.head.sha,
+        })
+    }
+
     fn fetch(
         &self,
         values @ [owner, repo]: &[&str; 2],
@@ -54,17 +163,17 @@ impl FetchFromGitea<'_> {
         if cfg.has_args() {
             self.fetch_fod(values, rev_key, rev, submodules, cfg)
         } else if submodules {
-            git_prefetch(
-                true,
-                &format!("git+https://{}/{owner}/{repo}", self.0),
+            cfg.prefetch(Source::git(
+                &format!("https://{}/{owner}/{repo}", self.0),
+                rev_key,
                 rev,
                 true,
-            )
-        } else {
-            flake_prefetch(format!(
-                "tarball+https://{}/{owner}/{repo}/archive/{rev}.tar.gz",
-                self.0
             ))
+        } else {
+            cfg.prefetch(Source::Tarball(format!(
+                "https://{}/{owner}/{repo}/archive/{rev}.tar.gz",
+                self.0,
+            )))
         }
     }
 }
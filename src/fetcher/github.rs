@@ -1,9 +1,13 @@
-use eyre::{Result, WrapErr};
+use eyre::{Result, WrapErr, bail};
 use serde::Deserialize;
+use ureq::{Body, RequestBuilder, typestate::WithoutBody};
 
 use crate::{
+    config::FetcherConfig,
+    credentials::{self, Forge},
+    fetcher::patch::CommitRange,
     impl_fetcher,
-    simple::{RevKey, SimpleFetcher, SimpleGitFetcher},
+    simple::{RevKey, SimpleFetcher, SimpleGitFetcher, Tag, paginate},
 };
 
 pub struct FetchFromGitHub<'a>(pub Option<&'a str>);
@@ -14,16 +18,29 @@ struct Commit {
     sha: String,
 }
 
-fn token() -> Option<String> {
-    ["GH_TOKEN", "GITHUB_TOKEN", "GITHUB_API_TOKEN"]
-        .iter()
-        .find_map(|key| std::env::var(key).ok())
+#[derive(Deserialize)]
+struct PullRequest {
+    base: Commit,
+    head: Commit,
+}
+
+#[derive(Deserialize)]
+struct GitTag {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    draft: bool,
+    prerelease: bool,
 }
 
 impl SimpleFetcher<'_, 2> for FetchFromGitHub<'_> {
     const HOST_KEY: &'static str = "githubBase";
     const KEYS: [&'static str; 2] = ["owner", "repo"];
     const NAME: &'static str = "fetchFromGitHub";
+    const PRIVATE_KEY: Option<&'static str> = Some("private");
     const REV_KEY: RevKey = RevKey::RevOrTag;
     const SUBMODULES_KEY: Option<&'static str> = Some("fetchSubmodules");
 
@@ -31,30 +48,134 @@ impl SimpleFetcher<'_, 2> for FetchFromGitHub<'_> {
         self.0
     }
 
-    fn fetch_rev(&self, [owner, repo]: &[&str; 2]) -> Result<String> {
-        let host = self.0.unwrap_or("github.com");
-        let url = format!("https://api.{host}/repos/{owner}/{repo}/commits?per_page=1");
+    fn check_private(&self) -> Result<()> {
+        let host = self.host_or_default();
+        if credentials::get(host, Some(Forge::GitHub)).is_none() {
+            bail!("private repositories require credentials for {host}, but none were found");
+        }
+        Ok(())
+    }
 
-        // https://docs.github.com/en/rest/authentication/authenticating-to-the-rest-api
-        let mut request = ureq::get(&url)
-            .header("Accept", "application/vnd.github+json")
-            .header("X-GitHub-Api-Version", "2022-11-28");
+    fn git_url(&self, values: &[&str; 2]) -> Option<String> {
+        Some(self.get_repo_url(values))
+    }
 
-        if let Some(token) = token() {
-            request = request.header("Authorization", &format!("Bearer {token}"));
-        }
+    fn fetch_rev(&self, [owner, repo]: &[&str; 2]) -> Result<String> {
+        let host = self.host_or_default();
+        let url = format!("{}/repos/{owner}/{repo}/commits?per_page=1", self.api_url());
 
-        let [Commit { sha }] = request
-            .call()?
-            .into_body()
+        let [Commit { sha }] = get(host, &url)?
             .read_json::<[_; 1]>()
             .wrap_err_with(|| format!("no commits found for https://{host}/{owner}/{repo}"))?;
 
         Ok(sha)
     }
+
+    fn expand_commit(&self, [owner, repo]: &[&str; 2], short: &str) -> Result<Option<String>> {
+        let host = self.host_or_default();
+        let url = format!("{}/repos/{owner}/{repo}/commits/{short}", self.api_url());
+
+        match request(host, &url).call() {
+            Ok(resp) => Ok(Some(resp.into_body().read_json::<Commit>()?.sha)),
+            Err(ureq::Error::StatusCode(404 | 422)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn fetch_tags(
+        &self,
+        [owner, repo]: &[&str; 2],
+        releases: bool,
+        cfg: &FetcherConfig,
+    ) -> Result<Vec<Tag>> {
+        let host = self.host_or_default();
+        let url = format!("{}/repos/{owner}/{repo}", self.api_url());
+
+        Ok(if releases {
+            paginate(100, cfg.jobs, |page| {
+                Ok(
+                    get(host, &format!("{url}/releases?per_page=100&page={page}"))?
+                        .read_json::<Vec<Release>>()?,
+                )
+            })?
+            .into_iter()
+            .filter(|release| !release.draft)
+            .map(|release| Tag {
+                name: release.tag_name,
+                prerelease: release.prerelease,
+            })
+            .collect()
+        } else {
+            paginate(100, cfg.jobs, |page| {
+                Ok(get(host, &format!("{url}/tags?per_page=100&page={page}"))?
+                    .read_json::<Vec<GitTag>>()?)
+            })?
+            .into_iter()
+            .map(|tag| Tag {
+                name: tag.name,
+                prerelease: false,
+            })
+            .collect()
+        })
+    }
+}
+
+impl FetchFromGitHub<'_> {
+    /// The commits of pull request `number`
+    pub fn pull_request(&self, owner: &str, repo: &str, number: &str) -> Result<CommitRange> {
+        let url = format!("{}/repos/{owner}/{repo}/pulls/{number}", self.api_url());
+        let pr = get(self.host_or_default(), &url)?.read_json::<PullRequest>()?;
+
+        Ok(CommitRange {
+            base: pr.base.sha,
+            head: pr.head.sha,
+        })
+    }
+
+    fn host_or_default(&self) -> &str {
+        self.0.unwrap_or("github.com")
+    }
+
+    /// The base URL of the REST API, which is under `/api/v3` on GitHub Enterprise Server
+    fn api_url(&self) -> String {
+        match self.0 {
+            Some(host) => format!("https://{host}/api/v3"),
+            None => "https://api.github.com".into(),
+        }
+    }
+}
+
+// https://docs.github.com/en/rest/authentication/authenticating-to-the-rest-api
+fn request(host: &str, url: &str) -> RequestBuilder<WithoutBody> {
+    let request = ureq::get(url)
+        .header("Accept", "application/vnd.github+json")
+        .header("X-GitHub-Api-Version", "2022-11-28");
+
+    match credentials::get(host, Some(Forge::GitHub)) {
+        Some(credential) => {
+            request.header("Authorization", &format!("Bearer {}", credential.secret))
+        }
+        None => request,
+    }
+}
+
+// private repositories look like they do not exist without a token
+fn get(host: &str, url: &str) -> Result<Body> {
+    match request(host, url).call() {
+        Ok(resp) => Ok(resp.into_body()),
+        Err(ureq::Error::StatusCode(404))
+            if credentials::get(host, Some(Forge::GitHub)).is_none() =>
+        {
+            bail!(
+                "{url} was not found, the repository might be private without credentials for {host}"
+            )
+        }
+        Err(e) => Err(e).wrap_err_with(|| format!("failed to request {url}")),
+    }
 }
 
 impl<'a> SimpleGitFetcher<'a, 2> for FetchFromGitHub<'a> {
+    // nix downloads from the tarball endpoint of the API under `/api/v3` for other hosts
     fn get_flake_ref(&self, [owner, repo]: &[&str; 2], rev: &str) -> String {
         if let Some(host) = self.0 {
             format!("github:{owner}/{repo}/{rev}?host={host}")
@@ -64,9 +185,6 @@ impl<'a> SimpleGitFetcher<'a, 2> for FetchFromGitHub<'a> {
     }
 
     fn get_repo_url(&self, [owner, repo]: &[&str; 2]) -> String {
-        format!(
-            "git+https://{}/{owner}/{repo}",
-            self.0.unwrap_or("github.com"),
-        )
+        format!("git+https://{}/{owner}/{repo}", self.host_or_default())
     }
 }
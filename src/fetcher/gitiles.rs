@@ -14,6 +14,10 @@ impl<'a> SimpleFetcher<'a, 1> for FetchFromGitiles {
     fn get_values(&self, url: &'a Url) -> Option<[&'a str; 1]> {
         Some([url.as_str()])
     }
+
+    fn git_url(&self, [url]: &[&str; 1]) -> Option<String> {
+        Some((*url).into())
+    }
 }
 
 impl SimpleUrlFetcher<'_, 1> for FetchFromGitiles {
@@ -1,16 +1,23 @@
-use std::{cell::OnceCell, fmt::Write, iter::once};
+use std::{cell::OnceCell, num::NonZeroUsize};
 
-use eyre::{Result, WrapErr};
+use eyre::{Result, WrapErr, bail, eyre};
 use serde::Deserialize;
+use ureq::{RequestBuilder, typestate::WithoutBody};
 
 use crate::{
-    Url, impl_fetcher,
-    simple::{RevKey, SimpleFetcher, SimpleGitFetcher},
+    Url,
+    config::FetcherConfig,
+    credentials::{self, Forge},
+    impl_fetcher,
+    prefetch::eprintln_tagged,
+    simple::{RevKey, SimpleFetcher, SimpleGitFetcher, Tag, paginate},
 };
 
 pub struct FetchFromGitLab<'a> {
     pub host: Option<&'a str>,
     pub group: OnceCell<&'a str>,
+    /// The group of the project found with the API, which takes precedence over `group`
+    resolved_group: OnceCell<Option<String>>,
 }
 impl_fetcher!(FetchFromGitLab<'a>);
 
@@ -19,8 +26,66 @@ impl<'a> FetchFromGitLab<'a> {
         Self {
             host,
             group: OnceCell::new(),
+            resolved_group: OnceCell::new(),
         }
     }
+
+    /// The full path of the project on `host` whose path is a prefix of `path`,
+    /// trying the shortest prefix first, or of the project with the ID in `projects/<id>`
+    pub fn find_project(host: &str, path: &str) -> Result<Option<String>> {
+        let candidates = match path.strip_prefix("projects/") {
+            Some(id) if !id.is_empty() && id.bytes().all(|c| c.is_ascii_digit()) => {
+                vec![id.to_owned()]
+            }
+            _ => {
+                let segments: Vec<_> = path.split('/').collect();
+                (2 ..= segments.len())
+                    .map(|n| segments[.. n].join("%2F"))
+                    .collect()
+            }
+        };
+
+        for candidate in candidates {
+            let url = format!("https://{host}/api/v4/projects/{candidate}");
+            match request(host, &url).call() {
+                Ok(resp) => {
+                    return Ok(Some(
+                        resp.into_body().read_json::<Project>()?.path_with_namespace,
+                    ));
+                }
+                // private projects are not found without credentials either
+                Err(ureq::Error::StatusCode(404)) => {}
+                Err(e) => return Err(e).wrap_err_with(|| format!("failed to request {url}")),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// The commits of merge request `iid` of the project at `path` on `host`, newest first
+    pub fn merge_request_commits(host: &str, path: &str, iid: &str) -> Result<Vec<String>> {
+        let url = format!(
+            "https://{host}/api/v4/projects/{}/merge_requests/{iid}/commits",
+            path.replace('/', "%2F"),
+        );
+
+        // merge requests rarely have more than one page of commits
+        Ok(paginate(100, NonZeroUsize::MIN, |page| {
+            Ok(request(host, &format!("{url}?per_page=100&page={page}"))
+                .call()
+                .wrap_err_with(|| format!("failed to request {url}"))?
+                .into_body()
+                .read_json::<Vec<Commit>>()?)
+        })?
+        .into_iter()
+        .map(|commit| commit.id)
+        .collect())
+    }
+}
+
+#[derive(Deserialize)]
+struct Project {
+    path_with_namespace: String,
 }
 
 #[derive(Deserialize)]
@@ -28,6 +93,18 @@ struct Commit {
     id: String,
 }
 
+#[derive(Deserialize)]
+struct GitTag {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    upcoming_release: bool,
+}
+
 impl<'a> SimpleFetcher<'a, 2> for FetchFromGitLab<'a> {
     const KEYS: [&'static str; 2] = ["owner", "repo"];
     const NAME: &'static str = "fetchFromGitLab";
@@ -39,24 +116,27 @@ impl<'a> SimpleFetcher<'a, 2> for FetchFromGitLab<'a> {
     }
 
     fn group(&self) -> Option<&str> {
-        self.group.get().copied()
+        match self.resolved_group.get() {
+            Some(group) => group.as_deref(),
+            None => self.group.get().copied(),
+        }
+    }
+
+    fn git_url(&self, values: &[&str; 2]) -> Option<String> {
+        Some(self.get_repo_url(values))
     }
 
     fn get_values(&self, url: &'a Url) -> Option<[&'a str; 2]> {
-        let mut i = 0;
-        for j in url
-            .path
-            .match_indices('/')
-            .map(|(j, _)| j)
-            .chain(once(url.path.len()))
-        {
-            if matches!(url.path.get(i + 1 .. j), Some("" | "-") | None) {
+        // the path of the project ends before `/-/` or an empty segment
+        let mut len = 0;
+        for segment in url.path.split('/') {
+            if matches!(segment, "" | "-") {
                 break;
             }
-            i = j;
+            len += segment.len() + 1;
         }
 
-        let (path, repo) = url.path[.. i].rsplit_once('/')?;
+        let (path, repo) = url.path.get(.. len.checked_sub(1)?)?.rsplit_once('/')?;
         let owner = match path.rsplit_once('/') {
             Some((group, owner)) => {
                 let _ = self.group.set(group);
@@ -68,23 +148,71 @@ impl<'a> SimpleFetcher<'a, 2> for FetchFromGitLab<'a> {
         Some([owner, repo.strip_suffix(".git").unwrap_or(repo)])
     }
 
-    fn fetch_rev(&self, [owner, repo]: &[&str; 2]) -> Result<String> {
-        let host = self.host.unwrap_or("gitlab.com");
-
-        let mut url = format!("https://{host}/api/v4/projects/");
+    // the last two segments of the URL are not the owner and the repo
+    // if it links to a file without `/-/`, and numeric IDs have to be looked up
+    fn resolve_values(
+        &self,
+        [owner, repo]: &[&str; 2],
+        cfg: &FetcherConfig,
+    ) -> Result<Option<[String; 2]>> {
+        let mut path = String::new();
         if let Some(group) = self.group.get() {
-            url.push_str(&group.replace('/', "%2F"));
-            url.push_str("%2F");
+            path.push_str(group);
+            path.push('/');
+        }
+        path.push_str(owner);
+        path.push('/');
+        path.push_str(repo);
+
+        let id = path.strip_prefix("projects/");
+        if id.is_none() && self.group.get().is_none() {
+            return Ok(None);
         }
-        write!(url, "{owner}%2F{repo}/repository/commits?per_page=1")?;
 
-        let [Commit { id }] = ureq::get(&url)
+        let full_path = match cfg.gitlab_project(self.api_host(), &path) {
+            Ok(Some(full_path)) => full_path,
+            Ok(None) => {
+                if let Some(id) = id {
+                    bail!("project {id} not found on {}", self.api_host());
+                }
+                return Ok(None);
+            }
+            // the path from the URL is right unless it links to a file without `/-/`
+            Err(e) if id.is_none() => {
+                eprintln_tagged(format_args!(
+                    "warning: failed to look up the project {path} on {}, using the path from the URL: {e:#}",
+                    self.api_host(),
+                ));
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let (namespace, repo) = full_path
+            .rsplit_once('/')
+            .ok_or_else(|| eyre!("invalid project path: {full_path}"))?;
+        let (group, owner) = match namespace.rsplit_once('/') {
+            Some((group, owner)) => (Some(group.into()), owner),
+            None => (None, namespace),
+        };
+        let _ = self.resolved_group.set(group);
+
+        Ok(Some([owner.into(), repo.into()]))
+    }
+
+    fn fetch_rev(&self, [owner, repo]: &[&str; 2]) -> Result<String> {
+        let url = format!(
+            "{}/repository/commits?per_page=1",
+            self.api_url(owner, repo),
+        );
+
+        let [Commit { id }] = request(self.api_host(), &url)
             .call()?
             .into_body()
             .read_json::<[_; 1]>()
             .wrap_err_with(|| {
-                let mut msg = format!("no commits found for https://{host}/");
-                if let Some(group) = self.group.get() {
+                let mut msg = format!("no commits found for https://{}/", self.api_host());
+                if let Some(group) = self.group() {
                     msg.push_str(group);
                     msg.push('/');
                 }
@@ -96,12 +224,93 @@ impl<'a> SimpleFetcher<'a, 2> for FetchFromGitLab<'a> {
 
         Ok(id)
     }
+
+    fn expand_commit(&self, [owner, repo]: &[&str; 2], short: &str) -> Result<Option<String>> {
+        let url = format!("{}/repository/commits/{short}", self.api_url(owner, repo),);
+
+        match request(self.api_host(), &url).call() {
+            Ok(resp) => Ok(Some(resp.into_body().read_json::<Commit>()?.id)),
+            Err(ureq::Error::StatusCode(404)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn fetch_tags(
+        &self,
+        [owner, repo]: &[&str; 2],
+        releases: bool,
+        cfg: &FetcherConfig,
+    ) -> Result<Vec<Tag>> {
+        let host = self.api_host();
+        let url = self.api_url(owner, repo);
+
+        Ok(if releases {
+            paginate(100, cfg.jobs, |page| {
+                Ok(
+                    request(host, &format!("{url}/releases?per_page=100&page={page}"))
+                        .call()?
+                        .into_body()
+                        .read_json::<Vec<Release>>()?,
+                )
+            })?
+            .into_iter()
+            .filter(|release| !release.upcoming_release)
+            .map(|release| Tag {
+                name: release.tag_name,
+                prerelease: false,
+            })
+            .collect()
+        } else {
+            paginate(100, cfg.jobs, |page| {
+                Ok(request(
+                    host,
+                    &format!("{url}/repository/tags?per_page=100&page={page}"),
+                )
+                .call()?
+                .into_body()
+                .read_json::<Vec<GitTag>>()?)
+            })?
+            .into_iter()
+            .map(|tag| Tag {
+                name: tag.name,
+                prerelease: false,
+            })
+            .collect()
+        })
+    }
+}
+
+impl FetchFromGitLab<'_> {
+    fn api_host(&self) -> &str {
+        self.host.unwrap_or("gitlab.com")
+    }
+
+    fn api_url(&self, owner: &str, repo: &str) -> String {
+        let mut url = format!("https://{}/api/v4/projects/", self.api_host());
+        if let Some(group) = self.group() {
+            url.push_str(&group.replace('/', "%2F"));
+            url.push_str("%2F");
+        }
+        url.push_str(owner);
+        url.push_str("%2F");
+        url.push_str(repo);
+        url
+    }
+}
+
+// https://docs.gitlab.com/api/rest/authentication/#personalprojectgroup-access-tokens
+fn request(host: &str, url: &str) -> RequestBuilder<WithoutBody> {
+    let request = ureq::get(url);
+    match credentials::get(host, Some(Forge::GitLab)) {
+        Some(credential) => request.header("PRIVATE-TOKEN", &credential.secret),
+        None => request,
+    }
 }
 
 impl<'a> SimpleGitFetcher<'a, 2> for FetchFromGitLab<'a> {
     fn get_flake_ref(&self, [owner, repo]: &[&str; 2], rev: &str) -> String {
         let mut flake_ref = String::from("gitlab:");
-        if let Some(group) = self.group.get() {
+        if let Some(group) = self.group() {
             flake_ref.push_str(&group.replace('/', "%252F"));
             flake_ref.push_str("%252F");
         }
@@ -121,7 +330,7 @@ impl<'a> SimpleGitFetcher<'a, 2> for FetchFromGitLab<'a> {
         let mut flake_ref = String::from("git+https://");
         flake_ref.push_str(self.host.unwrap_or("gitlab.com"));
         flake_ref.push('/');
-        if let Some(group) = self.group.get() {
+        if let Some(group) = self.group() {
             flake_ref.push_str(group);
             flake_ref.push('/');
         }
@@ -134,8 +343,12 @@ impl<'a> SimpleGitFetcher<'a, 2> for FetchFromGitLab<'a> {
 
 #[cfg(test)]
 mod tests {
+    use std::{fs, sync::Arc};
+
+    use tempfile::TempDir;
+
     use super::FetchFromGitLab;
-    use crate::{Url, simple::SimpleFetcher};
+    use crate::{Url, config::FetcherConfig, prefetch::Fixtures, simple::SimpleFetcher};
 
     #[test]
     fn basic() {
@@ -232,4 +445,38 @@ mod tests {
         };
         assert_eq!(fetcher.get_values(&url), None);
     }
+
+    #[test]
+    fn resolve() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("fixtures.json");
+        fs::write(
+            &path,
+            r#"{
+  "gitlab-project gitlab.com foo/bar": "other/project",
+  "gitlab-project gitlab.com foo/bar/baz": "foo/bar"
+}"#,
+        )
+        .unwrap();
+        let cfg = FetcherConfig {
+            prefetcher: Arc::new(Fixtures::replay(path).unwrap()),
+            ..Default::default()
+        };
+        let resolve = |path| {
+            let fetcher = FetchFromGitLab::new(None);
+            let url = Url { url: "", path };
+            let values = fetcher.get_values(&url).unwrap();
+            fetcher.resolve_values(&values, &cfg)
+        };
+
+        // paths with two segments are not looked up
+        assert_eq!(resolve("foo/bar").unwrap(), None);
+        assert_eq!(
+            resolve("foo/bar/baz").unwrap(),
+            Some(["foo".into(), "bar".into()]),
+        );
+        // failed lookups fall back to the path from the URL
+        assert_eq!(resolve("a/b/c").unwrap(), None);
+        assert!(resolve("projects/12").is_err());
+    }
 }
@@ -1,3 +1,8 @@
+use eyre::Result;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+use serde_json::Value;
+
 use crate::{
     Url, impl_fetcher,
     simple::{RevKey, SimpleFetcher, SimpleUrlFetcher},
@@ -6,6 +11,18 @@ use crate::{
 pub struct FetchHex;
 impl_fetcher!(FetchHex);
 
+#[derive(Deserialize)]
+struct Package {
+    releases: Vec<Release>,
+    #[serde(default)]
+    retirements: FxHashMap<String, Value>,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    version: String,
+}
+
 impl<'a> SimpleFetcher<'a, 1> for FetchHex {
     const HASH_KEY: &'static str = "sha256";
     const KEYS: [&'static str; 1] = ["pkg"];
@@ -15,6 +32,23 @@ impl<'a> SimpleFetcher<'a, 1> for FetchHex {
     fn get_values(&self, url: &'a Url) -> Option<[&'a str; 1]> {
         Some([url.path_segments().nth(1)?])
     }
+
+    // retired releases are the equivalent of yanked ones
+    fn fetch_versions(&self, [pkg]: &[&str; 1]) -> Result<Vec<String>> {
+        let Package {
+            releases,
+            retirements,
+        } = ureq::get(&format!("https://hex.pm/api/packages/{pkg}"))
+            .call()?
+            .into_body()
+            .read_json()?;
+
+        Ok(releases
+            .into_iter()
+            .map(|release| release.version)
+            .filter(|version| !retirements.contains_key(version))
+            .collect())
+    }
 }
 
 impl SimpleUrlFetcher<'_, 1> for FetchHex {
@@ -1,12 +1,19 @@
-use eyre::Result;
+use eyre::{Result, WrapErr};
+use serde::Deserialize;
 
 use crate::{
-    Url, config::FetcherConfig, impl_fetcher, prefetch::flake_prefetch, simple::SimpleFetcher,
+    Url, config::FetcherConfig, impl_fetcher, prefetch::Source, rev::is_commit_hash,
+    simple::SimpleFetcher,
 };
 
 pub struct Fetchhg(pub bool);
 impl_fetcher!(Fetchhg);
 
+#[derive(Deserialize)]
+struct Changeset {
+    node: String,
+}
+
 impl<'a> SimpleFetcher<'a, 1> for Fetchhg {
     const KEYS: [&'static str; 1] = ["url"];
     const NAME: &'static str = "fetchhg";
@@ -19,6 +26,39 @@ impl<'a> SimpleFetcher<'a, 1> for Fetchhg {
             url.as_str()
         }])
     }
+
+    // https://repo.mercurial-scm.org/hg/help/hgweb
+    // `default` is the head of the default branch, which is what cloning checks out,
+    // unlike `tip`, which is the newest changeset on any branch
+    fn fetch_rev(&self, [url]: &[&str; 1]) -> Result<String> {
+        let url = url.trim_end_matches('/');
+
+        match ureq::get(&format!("{url}/raw-rev/default")).call() {
+            Ok(resp) => {
+                if let Some(node) = parse_node_id(&resp.into_body().read_to_string()?) {
+                    return Ok(node.into());
+                }
+            }
+            // the raw style can be disabled, fall back to the json style
+            Err(ureq::Error::StatusCode(404)) => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let Changeset { node } = ureq::get(&format!("{url}/json-rev/default"))
+            .call()?
+            .into_body()
+            .read_json()
+            .wrap_err_with(|| format!("no changesets found for {url}"))?;
+        Ok(node)
+    }
+}
+
+fn parse_node_id(patch: &str) -> Option<&str> {
+    patch
+        .lines()
+        .take_while(|line| line.starts_with('#'))
+        .find_map(|line| line.strip_prefix("# Node ID "))
+        .map(str::trim)
 }
 
 impl Fetchhg {
@@ -33,10 +73,35 @@ impl Fetchhg {
         if cfg.has_args() || submodules {
             self.fetch_fod(values, rev_key, rev, submodules, cfg)
         } else {
-            flake_prefetch(format!(
+            cfg.prefetch(Source::Flake(format!(
                 "hg+{url}?{}={rev}",
-                if rev.len() == 40 { "rev" } else { "ref" },
-            ))
+                if is_commit_hash(rev) { "rev" } else { "ref" },
+            )))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::parse_node_id;
+
+    #[test]
+    fn node_id() {
+        let patch = "\
+# HG changeset patch
+# User nurl <nurl@example.org>
+# Date 1700000000 0
+#      Tue Nov 14 22:13:20 2023 +0000
+# Node ID 4f9b3c2a1d0e5f6a7b8c9d0e1f2a3b4c5d6e7f80
+# Parent  0a1b2c3d4e5f60718293a4b5c6d7e8f901234567
+first commit
+
+diff --git a/README b/README
+";
+        assert_eq!(
+            parse_node_id(patch),
+            Some("4f9b3c2a1d0e5f6a7b8c9d0e1f2a3b4c5d6e7f80"),
+        );
+        assert_eq!(parse_node_id("<!DOCTYPE html>"), None);
+    }
+}
@@ -22,6 +22,7 @@ use std::io::Write;
 use enum_dispatch::enum_dispatch;
 use eyre::Result;
 
+pub(crate) use self::patch::PatchPage;
 pub use self::{
     bitbucket::FetchFromBitbucket, builtin_git::BuiltinsFetchGit, crates_io::FetchCrate,
     git::Fetchgit, gitea::FetchFromGitea, github::FetchFromGitHub, gitiles::FetchFromGitiles,
@@ -29,7 +30,7 @@ pub use self::{
     pypi::FetchPypi, repo_or_cz::FetchFromRepoOrCz, sourcehut::FetchFromSourcehut, svn::Fetchsvn,
     url::Fetchurl, zip::Fetchzip,
 };
-use crate::{Url, config::FetcherConfig};
+use crate::{Url, config::FetcherConfig, output::FetcherOutput};
 
 #[enum_dispatch]
 pub trait Fetcher<'a> {
@@ -40,6 +41,8 @@ pub trait Fetcher<'a> {
     fn fetch_json(&self, out: &mut impl Write, url: &'a Url, cfg: FetcherConfig) -> Result<()>;
 
     fn to_json(&'a self, out: &mut impl Write, url: &'a Url, rev: Option<String>) -> Result<()>;
+
+    fn fetch_output(&self, url: &'a Url, cfg: FetcherConfig) -> Result<FetcherOutput>;
 }
 
 #[enum_dispatch(Fetcher)]
@@ -74,22 +77,8 @@ macro_rules! impl_fetcher {
                 url: &'a $crate::Url,
                 cfg: $crate::config::FetcherConfig,
             ) -> ::eyre::Result<()> {
-                use ::eyre::eyre;
-
-                let values = &self
-                    .get_values(url)
-                    .ok_or_else(|| eyre!("failed to parse {url}"))?;
-
-                let rev = match &cfg.rev {
-                    Some(rev) => rev.clone(),
-                    None => self.fetch_rev(values)?,
-                };
-                let (rev_key, rev) = self.rev_entry(&rev);
-
-                let submodules = self.resolve_submodules(cfg.submodules);
-                let hash = self.fetch(values, rev_key, rev, submodules, &cfg)?;
-
-                self.write_nix(out, values, rev_key, rev, hash, submodules, cfg)
+                let indent = " ".repeat(cfg.indent);
+                self.fetch_output(url, cfg)?.write_nix(out, &indent)
             }
 
             fn fetch_hash(
@@ -98,21 +87,10 @@ macro_rules! impl_fetcher {
                 url: &'a $crate::Url,
                 cfg: $crate::config::FetcherConfig,
             ) -> ::eyre::Result<()> {
-                use ::eyre::eyre;
-
-                let values = &self
-                    .get_values(url)
-                    .ok_or_else(|| eyre!("failed to parse {url}"))?;
-
-                let rev = match &cfg.rev {
-                    Some(rev) => rev,
-                    None => &self.fetch_rev(values)?,
-                };
-                let (rev_key, rev) = self.rev_entry(&rev);
-
-                let submodules = self.resolve_submodules(cfg.submodules);
-                let hash = self.fetch(values, rev_key, rev, submodules, &cfg)?;
-                write!(out, "{hash}")?;
+                match self.fetch_output(url, cfg)?.arg(Self::HASH_KEY) {
+                    Some($crate::output::Arg::String(hash)) => write!(out, "{hash}")?,
+                    _ => ::eyre::bail!("{} was overwritten with a Nix expression", Self::HASH_KEY),
+                }
 
                 Ok(())
             }
@@ -123,30 +101,8 @@ macro_rules! impl_fetcher {
                 url: &'a $crate::Url,
                 cfg: $crate::config::FetcherConfig,
             ) -> ::eyre::Result<()> {
-                use ::eyre::eyre;
-
-                let values = &self
-                    .get_values(url)
-                    .ok_or_else(|| eyre!("failed to parse {url}"))?;
-
-                let rev = match &cfg.rev {
-                    Some(rev) => rev.clone(),
-                    None => self.fetch_rev(values)?,
-                };
-                let (rev_key, rev) = self.rev_entry(&rev);
-
-                let submodules = self.resolve_submodules(cfg.submodules);
-                let hash = self.fetch(values, rev_key, rev, submodules, &cfg)?;
-
-                self.write_json(
-                    out,
-                    values,
-                    rev_key,
-                    rev,
-                    hash,
-                    submodules,
-                    cfg,
-                )
+                serde_json::to_writer(out, &self.fetch_output(url, cfg)?)?;
+                Ok(())
             }
 
             fn to_json(
@@ -185,6 +141,41 @@ macro_rules! impl_fetcher {
 
                 Ok(())
             }
+
+            fn fetch_output(
+                &self,
+                url: &'a $crate::Url,
+                cfg: $crate::config::FetcherConfig,
+            ) -> ::eyre::Result<$crate::output::FetcherOutput> {
+                use ::eyre::eyre;
+
+                let values = self
+                    .get_values(url)
+                    .ok_or_else(|| eyre!("failed to parse {url}"))?;
+                let resolved = self.resolve_values(&values, &cfg)?;
+                let values = &resolved
+                    .as_ref()
+                    .map_or(values, |values| values.each_ref().map(String::as_str));
+
+                let $crate::simple::Revision { rev, branch } = self.resolve_rev(values, &cfg)?;
+                let (rev_key, rev) = self.rev_entry(&rev);
+
+                let submodules = self.resolve_submodules(cfg.submodules);
+                let hash = cfg.cached(
+                    self.cache_key(values, rev_key, rev, submodules, &cfg)?,
+                    || self.fetch(values, rev_key, rev, submodules, &cfg),
+                )?;
+
+                Ok(self.get_output(
+                    values,
+                    rev_key,
+                    rev,
+                    hash,
+                    submodules,
+                    branch.as_deref(),
+                    &cfg,
+                ))
+            }
         }
     };
 }
@@ -1,13 +1,330 @@
-use eyre::Result;
+use eyre::{Result, bail, eyre};
 
-use crate::{Url, config::FetcherConfig, revless::RevlessFetcher};
+use crate::{
+    Url,
+    config::FetcherConfig,
+    credentials::{self, Forge},
+    fetcher::{FetchFromGitHub, FetchFromGitLab, FetchFromGitea},
+    rev::{RevKind, classify, is_commit_hash},
+    revless::RevlessFetcher,
+    simple::SimpleFetcher,
+};
 
-pub struct Fetchpatch;
+/// The forge of the host, if known, whose pages of commits, pull requests, and merge requests
+/// get turned into patches
+pub struct Fetchpatch(pub(crate) Option<Forge>);
 
 impl RevlessFetcher for Fetchpatch {
     const NAME: &'static str = "fetchpatch";
 
+    fn resolve_urls(&self, url: &str, cfg: Option<&FetcherConfig>) -> Result<Option<Vec<String>>> {
+        resolve_urls(url, self.0, cfg)
+    }
+
     fn fetch(&self, url: &Url, cfg: &FetcherConfig) -> Result<String> {
         self.fetch_fod(url, cfg)
     }
 }
+
+/// The commits of a pull request, compared from the merge base of `base` and `head`
+pub struct CommitRange {
+    pub base: String,
+    pub head: String,
+}
+
+/// A web page of a forge showing changes that can be fetched as a patch,
+/// like a commit, a pull request, or a merge request
+pub struct PatchPage<'a> {
+    url: &'a str,
+    forge: Forge,
+    /// The scheme and the authority of the URL
+    base: &'a str,
+    host: &'a str,
+    /// The path of the repository
+    repo: String,
+    change: Change<'a>,
+}
+
+#[derive(Clone, Copy)]
+enum Change<'a> {
+    Commit(&'a str),
+    Request(&'a str),
+}
+
+impl<'a> PatchPage<'a> {
+    /// Parse `url` as a page of `forge`, which has to be known from the host, since other forges
+    /// and plain git servers can have the same paths
+    pub fn parse(url: &'a str, forge: Forge) -> Option<Self> {
+        let (scheme, rest) = url.split_once("://")?;
+        if !matches!(scheme, "http" | "https") {
+            return None;
+        }
+
+        let (authority, path) = rest.split_once('/')?;
+        let path = path.split(['?', '#']).next()?;
+        if path.ends_with(".patch") || path.ends_with(".diff") {
+            return None;
+        }
+
+        let base = &url[.. scheme.len() + 3 + authority.len()];
+        let host = credentials::host(url)?;
+        let segments: Vec<_> = path.split('/').collect();
+
+        let (len, change) = match forge {
+            // GitLab separates the path of the project from the rest with `-`
+            Forge::GitLab => {
+                let i = segments.iter().position(|s| *s == "-")?;
+                let change = match &segments[i + 1 ..] {
+                    ["commit", sha, ..] => Change::Commit(sha),
+                    ["merge_requests", iid, ..] => Change::Request(iid),
+                    _ => return None,
+                };
+                (i, change)
+            }
+            Forge::GitHub => match segments.get(2 ..)? {
+                ["pull", _, "commits", sha, ..] | ["commit", sha, ..] => (2, Change::Commit(sha)),
+                ["pull", number, ..] => (2, Change::Request(number)),
+                _ => return None,
+            },
+            Forge::Gitea => match segments.get(2 ..)? {
+                ["pulls", _, "commits", sha, ..] | ["commit", sha, ..] => (2, Change::Commit(sha)),
+                ["pulls", number, ..] => (2, Change::Request(number)),
+                _ => return None,
+            },
+        };
+
+        let valid = match change {
+            Change::Commit(sha) => classify(sha) != RevKind::Ref,
+            Change::Request(number) => {
+                !number.is_empty() && number.bytes().all(|c| c.is_ascii_digit())
+            }
+        };
+
+        (valid && len >= 2).then(|| Self {
+            url,
+            forge,
+            base,
+            host,
+            repo: segments[.. len].join("/"),
+            change,
+        })
+    }
+
+    pub fn url(&self) -> &str {
+        self.url
+    }
+
+    /// Look up the commits of the pull request or merge request, or the full hash of the
+    /// abbreviated commit hash, with the API of the forge, returning the URLs of their patches
+    ///
+    /// Merge requests with several commits have one patch for each commit,
+    /// since GitLab does not serve patches of comparisons
+    pub fn pin(&self) -> Result<Vec<String>> {
+        let number = match self.change {
+            Change::Commit(sha) if is_commit_hash(sha) => return Ok(vec![self.commit_url(sha)]),
+            Change::Commit(short) => return Ok(vec![self.commit_url(&self.expand_commit(short)?)]),
+            Change::Request(number) => number,
+        };
+        let (owner, repo) = self.repo.split_once('/').unwrap_or_default();
+
+        match self.forge {
+            Forge::GitHub => {
+                let host = (self.host != "github.com").then_some(self.host);
+                let range = FetchFromGitHub(host).pull_request(owner, repo, number)?;
+                Ok(vec![self.compare_url(&range)])
+            }
+            Forge::GitLab => {
+                let commits =
+                    FetchFromGitLab::merge_request_commits(self.host, &self.repo, number)?;
+                if commits.is_empty() {
+                    bail!("merge request !{number} of {} has no commits", self.repo);
+                }
+                // the API lists the newest commit first
+                Ok(commits
+                    .iter()
+                    .rev()
+                    .map(|sha| self.commit_url(sha))
+                    .collect())
+            }
+            Forge::Gitea => {
+                let range = FetchFromGitea(self.host).pull_request(owner, repo, number)?;
+                Ok(vec![self.compare_url(&range)])
+            }
+        }
+    }
+
+    // abbreviated commit hashes in patch URLs break once they become ambiguous
+    fn expand_commit(&self, short: &str) -> Result<String> {
+        let mut segments = self.repo.rsplitn(3, '/');
+        let (Some(repo), Some(owner)) = (segments.next(), segments.next()) else {
+            bail!("invalid repository {}", self.repo);
+        };
+        let values = &[owner, repo];
+
+        match self.forge {
+            Forge::GitHub => FetchFromGitHub((self.host != "github.com").then_some(self.host))
+                .expand_commit(values, short)?,
+            Forge::GitLab => {
+                let fetcher = FetchFromGitLab::new(Some(self.host));
+                if let Some(group) = segments.next() {
+                    let _ = fetcher.group.set(group);
+                }
+                fetcher.expand_commit(values, short)?
+            }
+            Forge::Gitea => FetchFromGitea(self.host).expand_commit(values, short)?,
+        }
+        .ok_or_else(|| eyre!("commit {short} not found in {}/{}", self.base, self.repo))
+    }
+
+    // full_index makes GitHub output full object names, which do not change when abbreviations
+    // would become ambiguous
+    fn commit_url(&self, sha: &str) -> String {
+        match self.forge {
+            Forge::GitHub => format!(
+                "{}/{}/commit/{sha}.patch?full_index=1",
+                self.base, self.repo
+            ),
+            Forge::GitLab => format!("{}/{}/-/commit/{sha}.patch", self.base, self.repo),
+            Forge::Gitea => format!("{}/{}/commit/{sha}.patch", self.base, self.repo),
+        }
+    }
+
+    fn compare_url(&self, CommitRange { base, head }: &CommitRange) -> String {
+        let url = format!("{}/{}/compare/{base}...{head}.patch", self.base, self.repo);
+        match self.forge {
+            Forge::GitHub => url + "?full_index=1",
+            _ => url,
+        }
+    }
+}
+
+/// Turn pages of commits, pull requests, and merge requests of `forge` into URLs of patches
+/// pinned to commits, looking up pull requests, merge requests, and abbreviated commit hashes
+/// when `cfg` is given
+pub fn resolve_urls(
+    url: &str,
+    forge: Option<Forge>,
+    cfg: Option<&FetcherConfig>,
+) -> Result<Option<Vec<String>>> {
+    let Some(page) = forge.and_then(|forge| PatchPage::parse(url, forge)) else {
+        return Ok(None);
+    };
+
+    match (page.change, cfg) {
+        (Change::Commit(sha), _) if is_commit_hash(sha) => Ok(Some(vec![page.commit_url(sha)])),
+        (_, Some(cfg)) => cfg.pin_patch(&page).map(Some),
+        (_, None) => {
+            bail!("{url} needs to be looked up to pin it to commits, which --parse does not do")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use tempfile::TempDir;
+
+    use super::{PatchPage, resolve_urls};
+    use crate::{config::FetcherConfig, credentials::Forge, prefetch::Fixtures};
+
+    #[test]
+    fn pages() {
+        for (url, forge, expected) in [
+            (
+                "https://github.com/nix-community/nurl/commit/4d5515e112addf88b4107dd9357243dc10624e58",
+                Forge::GitHub,
+                Some(
+                    "https://github.com/nix-community/nurl/commit/4d5515e112addf88b4107dd9357243dc10624e58.patch?full_index=1",
+                ),
+            ),
+            (
+                "https://github.com/o/r/pull/12/commits/4d5515e112addf88b4107dd9357243dc10624e58",
+                Forge::GitHub,
+                Some(
+                    "https://github.com/o/r/commit/4d5515e112addf88b4107dd9357243dc10624e58.patch?full_index=1",
+                ),
+            ),
+            (
+                "https://gitlab.com/a/b/c/-/commit/4d5515e112addf88b4107dd9357243dc10624e58?view=parallel",
+                Forge::GitLab,
+                Some(
+                    "https://gitlab.com/a/b/c/-/commit/4d5515e112addf88b4107dd9357243dc10624e58.patch",
+                ),
+            ),
+            (
+                "https://codeberg.org/o/r/commit/4d5515e112addf88b4107dd9357243dc10624e58",
+                Forge::Gitea,
+                Some(
+                    "https://codeberg.org/o/r/commit/4d5515e112addf88b4107dd9357243dc10624e58.patch",
+                ),
+            ),
+            (
+                "https://github.com/nix-community/nurl/commit/4d5515e112addf88b4107dd9357243dc10624e58.patch",
+                Forge::GitHub,
+                None,
+            ),
+            ("https://github.com/o/r/commit/main", Forge::GitHub, None),
+            ("https://github.com/o/r/tree/v1.2", Forge::GitHub, None),
+            ("git+https://github.com/o/r/pull/1", Forge::GitHub, None),
+            ("https://codeberg.org/o/r/pull/1", Forge::Gitea, None),
+            (
+                "https://gitlab.com/a/b/commit/4d5515e112addf88b4107dd9357243dc10624e58",
+                Forge::GitLab,
+                None,
+            ),
+        ] {
+            assert_eq!(
+                resolve_urls(url, Some(forge), None).unwrap(),
+                expected.map(|url| vec![url.to_owned()]),
+                "{url}",
+            );
+        }
+
+        for (url, forge) in [
+            ("https://github.com/o/r/pull/123/files", Forge::GitHub),
+            (
+                "https://gitlab.com/a/b/c/-/merge_requests/45",
+                Forge::GitLab,
+            ),
+            ("https://codeberg.org/o/r/pulls/6", Forge::Gitea),
+            ("https://gitlab.com/a/b/c/-/commit/4d5515e1", Forge::GitLab),
+        ] {
+            assert!(PatchPage::parse(url, forge).is_some(), "{url}");
+            assert!(resolve_urls(url, Some(forge), None).is_err(), "{url}");
+        }
+        assert!(PatchPage::parse("https://github.com/o/r/pull/main", Forge::GitHub).is_none());
+
+        // the paths of pages are only known once the forge is known
+        let url = "https://git.example.com/o/r/pull/1";
+        assert_eq!(resolve_urls(url, None, None).unwrap(), None);
+    }
+
+    #[test]
+    fn recorded() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("fixtures.json");
+        fs::write(
+            &path,
+            r#"{
+                "patch https://github.com/o/r/pull/1": [],
+                "patch https://github.com/o/r/pull/2": ["a.patch", "b.patch"]
+            }"#,
+        )
+        .unwrap();
+        let cfg = FetcherConfig {
+            prefetcher: Arc::new(Fixtures::replay(path).unwrap()),
+            ..Default::default()
+        };
+
+        for (url, expected) in [
+            ("https://github.com/o/r/pull/1", vec![]),
+            ("https://github.com/o/r/pull/2", vec!["a.patch", "b.patch"]),
+        ] {
+            assert_eq!(
+                resolve_urls(url, Some(Forge::GitHub), Some(&cfg)).unwrap(),
+                Some(expected.into_iter().map(Into::into).collect()),
+            );
+        }
+    }
+}
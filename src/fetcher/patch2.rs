@@ -1,12 +1,20 @@
 use eyre::Result;
 
-use crate::{Url, config::FetcherConfig, revless::RevlessFetcher};
+use crate::{
+    Url, config::FetcherConfig, credentials::Forge, fetcher::patch::resolve_urls,
+    revless::RevlessFetcher,
+};
 
-pub struct Fetchpatch2;
+/// Like [`Fetchpatch`](super::Fetchpatch)
+pub struct Fetchpatch2(pub(crate) Option<Forge>);
 
 impl RevlessFetcher for Fetchpatch2 {
     const NAME: &'static str = "fetchpatch2";
 
+    fn resolve_urls(&self, url: &str, cfg: Option<&FetcherConfig>) -> Result<Option<Vec<String>>> {
+        resolve_urls(url, self.0, cfg)
+    }
+
     fn fetch(&self, url: &Url, cfg: &FetcherConfig) -> Result<String> {
         self.fetch_fod(url, cfg)
     }
@@ -1,16 +1,30 @@
 use eyre::Result;
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
 
 use crate::{
     Url,
     config::FetcherConfig,
     impl_fetcher,
-    prefetch::url_prefetch,
+    prefetch::Source,
     simple::{RevKey, SimpleFetcher},
 };
 
 pub struct FetchPypi;
 impl_fetcher!(FetchPypi);
 
+#[derive(Deserialize)]
+struct Project {
+    releases: FxHashMap<String, Vec<File>>,
+}
+
+#[derive(Deserialize)]
+struct File {
+    packagetype: String,
+    #[serde(default)]
+    yanked: bool,
+}
+
 impl<'a> SimpleFetcher<'a, 1> for FetchPypi {
     const KEYS: [&'static str; 1] = ["pname"];
     const NAME: &'static str = "fetchPypi";
@@ -20,6 +34,24 @@ impl<'a> SimpleFetcher<'a, 1> for FetchPypi {
         let pname = url.path_segments().nth(1)?;
         (!pname.is_empty()).then_some([pname])
     }
+
+    // only versions with a source distribution can be fetched by fetchPypi
+    fn fetch_versions(&self, [pname]: &[&str; 1]) -> Result<Vec<String>> {
+        let Project { releases } = ureq::get(&format!("https://pypi.org/pypi/{pname}/json"))
+            .call()?
+            .into_body()
+            .read_json()?;
+
+        Ok(releases
+            .into_iter()
+            .filter(|(_, files)| {
+                files
+                    .iter()
+                    .any(|file| file.packagetype == "sdist" && !file.yanked)
+            })
+            .map(|(version, _)| version)
+            .collect())
+    }
 }
 
 impl FetchPypi {
@@ -32,12 +64,12 @@ impl FetchPypi {
         cfg: &FetcherConfig,
     ) -> Result<String> {
         if !cfg.has_args() {
-            url_prefetch(&get_url(pname, version, "tar.gz"))
+            cfg.prefetch(Source::File(get_url(pname, version, "tar.gz")))
         } else if cfg.args.is_empty()
             && cfg.args_str.len() == 1
             && let Some(ext) = cfg.args.get("extension")
         {
-            url_prefetch(&get_url(pname, version, ext))
+            cfg.prefetch(Source::File(get_url(pname, version, ext)))
         } else {
             self.fetch_fod(values, rev_key, version, submodules, cfg)
         }
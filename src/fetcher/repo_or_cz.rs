@@ -9,6 +9,10 @@ impl_fetcher!(FetchFromRepoOrCz);
 impl SimpleFetcher<'_, 1> for FetchFromRepoOrCz {
     const KEYS: [&'static str; 1] = ["repo"];
     const NAME: &'static str = "fetchFromRepoOrCz";
+
+    fn git_url(&self, [repo]: &[&str; 1]) -> Option<String> {
+        Some(format!("https://repo.or.cz/{repo}.git"))
+    }
 }
 
 impl SimpleUrlFetcher<'_, 1> for FetchFromRepoOrCz {
@@ -15,6 +15,10 @@ impl<'a> SimpleFetcher<'a, 2> for FetchFromSourcehut<'a> {
     fn host(&self) -> Option<&str> {
         self.0
     }
+
+    fn git_url(&self, values: &[&str; 2]) -> Option<String> {
+        Some(self.get_repo_url(values))
+    }
 }
 
 impl<'a> SimpleGitFetcher<'a, 2> for FetchFromSourcehut<'a> {
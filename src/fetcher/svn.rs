@@ -1,3 +1,6 @@
+use eyre::{Result, bail, eyre};
+use ureq::{Agent, http::Request};
+
 use crate::{
     Url, impl_fetcher,
     simple::{SimpleFetcher, SimpleFodFetcher},
@@ -13,6 +16,68 @@ impl<'a> SimpleFetcher<'a, 1> for Fetchsvn {
     fn get_values(&self, url: &'a Url) -> Option<[&'a str; 1]> {
         Some([url.as_str()])
     }
+
+    // the version name of a node is the revision it was last changed in,
+    // like the last changed revision from `svn info`
+    fn fetch_rev(&self, [url]: &[&str; 1]) -> Result<String> {
+        if !(url.starts_with("https://") || url.starts_with("http://")) {
+            bail!("fetching the latest revision of {url} is only supported over HTTP(S)");
+        }
+
+        let agent: Agent = Agent::config_builder()
+            .allow_non_standard_methods(true)
+            .build()
+            .into();
+        let multistatus = agent
+            .run(
+                Request::builder()
+                    .method("PROPFIND")
+                    .uri(*url)
+                    .header("Depth", "0")
+                    .header("Content-Type", "text/xml")
+                    .body(
+                        r#"<?xml version="1.0" encoding="utf-8"?><propfind xmlns="DAV:"><prop><version-name/></prop></propfind>"#,
+                    )?,
+            )?
+            .into_body()
+            .read_to_string()?;
+
+        parse_version_name(&multistatus)
+            .map(Into::into)
+            .ok_or_else(|| eyre!("no revision found for {url}"))
+    }
 }
 
 impl SimpleFodFetcher<'_, 1> for Fetchsvn {}
+
+// the element can have any namespace prefix, like <lp1:version-name>
+fn parse_version_name(multistatus: &str) -> Option<&str> {
+    let (_, rest) = multistatus.split_once("version-name>")?;
+    let (rev, _) = rest.split_once('<')?;
+    let rev = rev.trim();
+    rev.bytes().all(|c| c.is_ascii_digit()).then_some(rev)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_version_name;
+
+    #[test]
+    fn version_name() {
+        let multistatus = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:multistatus xmlns:D="DAV:" xmlns:ns0="DAV:">
+<D:response xmlns:lp1="DAV:" xmlns:lp2="http://subversion.tigris.org/xmlns/dav/">
+<D:href>/repos/project/trunk/</D:href>
+<D:propstat>
+<D:prop>
+<lp1:version-name>1918</lp1:version-name>
+</D:prop>
+<D:status>HTTP/1.1 200 OK</D:status>
+</D:propstat>
+</D:response>
+</D:multistatus>
+"#;
+        assert_eq!(parse_version_name(multistatus), Some("1918"));
+        assert_eq!(parse_version_name("<lp1:version-name/>"), None);
+    }
+}
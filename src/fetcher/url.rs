@@ -1,6 +1,6 @@
 use eyre::Result;
 
-use crate::{Url, config::FetcherConfig, prefetch::url_prefetch, revless::RevlessFetcher};
+use crate::{Url, config::FetcherConfig, prefetch::Source, revless::RevlessFetcher};
 
 pub struct Fetchurl;
 
@@ -11,7 +11,7 @@ impl RevlessFetcher for Fetchurl {
         if cfg.has_args() {
             self.fetch_fod(url, cfg)
         } else {
-            url_prefetch(url.as_str())
+            cfg.prefetch(Source::File(url.to_string()))
         }
     }
 }
@@ -0,0 +1,406 @@
+mod archive;
+pub mod batch;
+pub mod cache;
+pub mod cli;
+pub mod config;
+mod credentials;
+mod detect;
+pub mod fetcher;
+mod ls_refs;
+pub mod nar;
+pub mod output;
+pub mod prefetch;
+mod rev;
+mod revless;
+pub mod settings;
+mod simple;
+pub mod update;
+mod version;
+
+use std::{
+    fmt::{self, Display, Formatter},
+    str::Split,
+};
+
+use bstr::ByteSlice;
+use eyre::{Result, bail};
+use gix_url::Scheme;
+use rustc_hash::FxHashMap;
+
+pub use crate::{
+    cli::FetcherFunction,
+    config::FetcherConfig,
+    fetcher::{Fetcher, FetcherDispatch},
+    output::{Arg, FetcherOutput},
+    rev::UrlRev,
+};
+use crate::{
+    credentials::Forge,
+    detect::detect_forge,
+    fetcher::{
+        BuiltinsFetchGit, FetchCrate, FetchFromBitbucket, FetchFromGitHub, FetchFromGitLab,
+        FetchFromGitea, FetchFromGitiles, FetchFromRepoOrCz, FetchFromSourcehut, FetchHex,
+        FetchPypi, Fetchgit, Fetchhg, Fetchpatch, Fetchpatch2, Fetchsvn, Fetchurl, Fetchzip,
+        PatchPage,
+    },
+};
+
+pub struct Url<'a> {
+    url: &'a str,
+    path: &'a str,
+}
+
+impl<'a> Url<'a> {
+    /// `path` is the path of `url` without the leading slash, the query string, and the fragment,
+    /// as returned by [`url_path`]
+    pub fn new(url: &'a str, path: &'a str) -> Self {
+        Self { url, path }
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.url
+    }
+
+    fn path_segments(&self) -> Split<'_, char> {
+        self.path.split('/')
+    }
+}
+
+impl Display for Url<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How fetchers are inferred from URLs
+#[derive(Clone)]
+pub struct Inference {
+    /// The fetcher to fall back to when nurl fails to infer it from the URL
+    pub fallback: FetcherFunction,
+    /// The fetcher for URLs of patches, either fetchpatch or fetchpatch2
+    pub patch: FetcherFunction,
+    /// Fetchers for hosts that nurl does not know about
+    pub hosts: FxHashMap<String, FetcherFunction>,
+    /// Probe the APIs of unknown hosts to detect the forge they run
+    pub detect: bool,
+}
+
+impl Default for Inference {
+    fn default() -> Self {
+        Self {
+            fallback: FetcherFunction::Fetchgit,
+            patch: FetcherFunction::Fetchpatch,
+            hosts: FxHashMap::default(),
+            detect: false,
+        }
+    }
+}
+
+pub enum GitScheme {
+    Yes,
+    No,
+    Plus,
+}
+
+/// Get the hash of a URL, inferring the fetcher from the URL unless `fetcher` is specified
+///
+/// Overwrites in `cfg` are not applied to the output
+pub fn prefetch(
+    url: &str,
+    fetcher: Option<FetcherFunction>,
+    inference: &Inference,
+    mut cfg: FetcherConfig,
+) -> Result<FetcherOutput> {
+    let (url, url_rev) = split_url(url, fetcher, inference);
+    cfg.rev = cfg.rev.or(url_rev.rev);
+    cfg.submodules = cfg.submodules.or(url_rev.submodules);
+
+    let url: gix_url::Url = url.as_str().try_into()?;
+    let path = url_path(&url)?;
+    let fetcher = infer_fetcher(&url, path, fetcher, inference)?;
+
+    let url_bstring = url.to_bstring();
+    let url = Url::new(url_bstring.to_str()?, path);
+
+    fetcher.fetch_output(&url, cfg)
+}
+
+/// Split the revision out of `url` if it is fetched with a fetcher of a forge or git, leaving
+/// URLs of downloads and patches, which can have fragments and paths of their own, as they are
+pub fn split_url(
+    url: &str,
+    fetcher: Option<FetcherFunction>,
+    inference: &Inference,
+) -> (String, UrlRev) {
+    let splits = gix_url::Url::try_from(url).ok().is_some_and(|parsed| {
+        url_path(&parsed)
+            .and_then(|path| infer_fetcher(&parsed, path, fetcher, inference))
+            .is_ok_and(|fetcher| {
+                matches!(
+                    fetcher,
+                    FetcherDispatch::BuiltinsFetchGit(_)
+                        | FetcherDispatch::FetchFromBitbucket(_)
+                        | FetcherDispatch::FetchFromGitHub(_)
+                        | FetcherDispatch::FetchFromGitLab(_)
+                        | FetcherDispatch::FetchFromGitea(_)
+                        | FetcherDispatch::FetchFromGitiles(_)
+                        | FetcherDispatch::FetchFromRepoOrCz(_)
+                        | FetcherDispatch::FetchFromSourcehut(_)
+                        | FetcherDispatch::Fetchgit(_),
+                )
+            })
+    });
+
+    if splits {
+        rev::split_url(url)
+    } else {
+        (url.into(), UrlRev::default())
+    }
+}
+
+/// The forge of `url` if it is a page of a commit, pull request, or merge request that is fetched
+/// as a patch, which is only known once the forge of the host is known from the settings, the
+/// well-known hosts, or detection, since plain git servers can have the same paths
+fn patch_page(url: &str, fetcher: Option<FetcherFunction>, inference: &Inference) -> Option<Forge> {
+    if !matches!(
+        fetcher,
+        None | Some(FetcherFunction::Fetchpatch | FetcherFunction::Fetchpatch2),
+    ) || !(url.starts_with("http://") || url.starts_with("https://"))
+    {
+        return None;
+    }
+
+    let host = credentials::host(url)?;
+    let fetcher = match inference.hosts.get(host) {
+        Some(fetcher) => *fetcher,
+        None => match host {
+            "github.com" => FetcherFunction::FetchFromGitHub,
+            "gitlab.com" | "framagit.org" | "invent.kde.org" | "salsa.debian.org" => {
+                FetcherFunction::FetchFromGitLab
+            }
+            _ if host.starts_with("gitlab.") => FetcherFunction::FetchFromGitLab,
+            "codeberg.org" | "git.lix.systems" | "gitea.com" | "notabug.org"
+            | "repo.palemoon.org" => FetcherFunction::FetchFromGitea,
+            _ if inference.detect => detect_forge(host)?,
+            _ => return None,
+        },
+    };
+
+    let forge = match fetcher {
+        FetcherFunction::FetchFromGitHub => Forge::GitHub,
+        FetcherFunction::FetchFromGitLab => Forge::GitLab,
+        FetcherFunction::FetchFromGitea => Forge::Gitea,
+        _ => return None,
+    };
+    PatchPage::parse(url, forge).map(|_| forge)
+}
+
+pub fn url_path(url: &gix_url::Url) -> Result<&str> {
+    let path = url.path.to_str()?;
+    let path = path.strip_prefix('/').unwrap_or(path);
+    Ok(path.split(['?', '#']).next().unwrap_or(path))
+}
+
+pub fn infer_fetcher<'a>(
+    url: &'a gix_url::Url,
+    path: &str,
+    fetcher: Option<FetcherFunction>,
+    inference: &Inference,
+) -> Result<FetcherDispatch<'a>> {
+    let forge = url
+        .to_bstring()
+        .to_str()
+        .ok()
+        .and_then(|url| patch_page(url, fetcher, inference));
+    let is_patch = path.ends_with(".diff") || path.ends_with(".patch") || forge.is_some();
+
+    // hosts from the settings take precedence over everything but patches and archives
+    let fetcher = fetcher.or_else(|| {
+        if is_patch || is_archive(path) {
+            None
+        } else {
+            url.host()
+                .and_then(|host| inference.hosts.get(host))
+                .copied()
+        }
+    });
+
+    Ok(match (fetcher, url.host(), &url.scheme) {
+        // high priority
+
+        // prefer fetchpatch over fetchpatch2 by default: https://github.com/NixOS/nixpkgs/issues/257446
+        (None, ..) if is_patch => match inference.patch {
+            FetcherFunction::Fetchpatch2 => Fetchpatch2(forge).into(),
+            _ => Fetchpatch(forge).into(),
+        },
+
+        (None, ..) if is_archive(path) => Fetchzip.into(),
+
+        // low priority
+        (Some(FetcherFunction::BuiltinsFetchGit), ..) => BuiltinsFetchGit.into(),
+
+        (None | Some(FetcherFunction::FetchCrate), Some("crates.io"), _) => FetchCrate(true).into(),
+        (None | Some(FetcherFunction::FetchCrate), Some("lib.rs"), _) => FetchCrate(false).into(),
+        (Some(FetcherFunction::FetchCrate), ..) => {
+            bail!("fetchCrate only supports crates.io and lib.rs");
+        }
+
+        (None | Some(FetcherFunction::FetchFromBitbucket), Some("bitbucket.org"), _) => {
+            FetchFromBitbucket.into()
+        }
+        (Some(FetcherFunction::FetchFromBitbucket), ..) => {
+            bail!("fetchFromBitbucket only supports bitbucket.org");
+        }
+
+        (None | Some(FetcherFunction::FetchFromGitHub), Some("github.com"), _) => {
+            FetchFromGitHub(None).into()
+        }
+        (Some(FetcherFunction::FetchFromGitHub), Some(host), _) => {
+            FetchFromGitHub(Some(host)).into()
+        }
+
+        (None | Some(FetcherFunction::FetchFromGitLab), Some("gitlab.com"), _) => {
+            FetchFromGitLab::new(None).into()
+        }
+        (None, Some(host), _) if host.starts_with("gitlab.") => {
+            FetchFromGitLab::new(Some(host)).into()
+        }
+        (None, Some(host @ ("framagit.org" | "invent.kde.org" | "salsa.debian.org")), _) => {
+            FetchFromGitLab::new(Some(host)).into()
+        }
+        (Some(FetcherFunction::FetchFromGitLab), Some(host), _) => {
+            FetchFromGitLab::new(Some(host)).into()
+        }
+
+        (
+            None | Some(FetcherFunction::FetchFromGitea),
+            Some(
+                host @ ("codeberg.org" | "git.lix.systems" | "gitea.com" | "notabug.org"
+                | "repo.palemoon.org"),
+            ),
+            _,
+        ) => FetchFromGitea(host).into(),
+        (Some(FetcherFunction::FetchFromGitea), Some(host), _) => FetchFromGitea(host).into(),
+
+        (None | Some(FetcherFunction::FetchFromGitiles), Some(host), _)
+            if host.ends_with(".googlesource.com") =>
+        {
+            FetchFromGitiles.into()
+        }
+        (Some(FetcherFunction::FetchFromGitiles), ..) => FetchFromGitiles.into(),
+
+        (None | Some(FetcherFunction::FetchFromRepoOrCz), Some("repo.or.cz"), _) => {
+            FetchFromRepoOrCz.into()
+        }
+        (Some(FetcherFunction::FetchFromRepoOrCz), ..) => {
+            bail!("fetchFromRepoOrCz only supports repo.or.cz");
+        }
+
+        (None | Some(FetcherFunction::FetchFromSourcehut), Some("git.sr.ht"), _) => {
+            FetchFromSourcehut(None).into()
+        }
+        (Some(FetcherFunction::FetchFromSourcehut), Some(host), _) => {
+            FetchFromSourcehut(Some(host)).into()
+        }
+
+        (
+            Some(
+                fetcher @ (FetcherFunction::FetchFromGitHub
+                | FetcherFunction::FetchFromGitLab
+                | FetcherFunction::FetchFromGitea
+                | FetcherFunction::FetchFromSourcehut),
+            ),
+            None,
+            _,
+        ) => {
+            bail!("{fetcher:?} does not support URLs without a host");
+        }
+
+        (None | Some(FetcherFunction::FetchHex), Some("hex.pm"), _) => FetchHex.into(),
+        (Some(FetcherFunction::FetchHex), ..) => {
+            bail!("fetchHex only supports hex.pm");
+        }
+
+        (None | Some(FetcherFunction::FetchPypi), Some("pypi.org"), _) => FetchPypi.into(),
+        (Some(FetcherFunction::FetchPypi), ..) => {
+            bail!("fetchPypi only supports pypi.org");
+        }
+
+        (None | Some(FetcherFunction::Fetchgit), _, Scheme::Git) => Fetchgit(GitScheme::Yes).into(),
+        (None | Some(FetcherFunction::Fetchgit), _, Scheme::Ext(scheme))
+            if scheme.starts_with("git+") =>
+        {
+            Fetchgit(GitScheme::Plus).into()
+        }
+        (Some(FetcherFunction::Fetchgit), ..) => Fetchgit(GitScheme::No).into(),
+
+        (None | Some(FetcherFunction::Fetchhg), _, Scheme::Ext(scheme))
+            if scheme.starts_with("hg+") =>
+        {
+            Fetchhg(true).into()
+        }
+        (Some(FetcherFunction::Fetchhg), ..) => Fetchhg(false).into(),
+
+        (Some(FetcherFunction::Fetchpatch), ..) => Fetchpatch(forge).into(),
+
+        (Some(FetcherFunction::Fetchpatch2), ..) => Fetchpatch2(forge).into(),
+
+        (None, _, Scheme::Ext(scheme)) if scheme == "svn" => Fetchsvn.into(),
+        (Some(FetcherFunction::Fetchsvn), ..) => Fetchsvn.into(),
+
+        (Some(FetcherFunction::Fetchurl), ..) => Fetchurl.into(),
+
+        (Some(FetcherFunction::Fetchzip), ..) => Fetchzip.into(),
+
+        (None, host, scheme) => {
+            if inference.detect
+                && matches!(scheme, Scheme::Http | Scheme::Https)
+                && let Some(fetcher) = host.and_then(detect_forge)
+            {
+                return infer_fetcher(url, path, Some(fetcher), inference);
+            }
+
+            match inference.fallback {
+                FetcherFunction::BuiltinsFetchGit => BuiltinsFetchGit.into(),
+                FetcherFunction::FetchCrate => {
+                    bail!("fetchCrate only supports crates.io and lib.rs");
+                }
+                FetcherFunction::FetchFromBitbucket => {
+                    bail!("fetchFromBitbucket only supports bitbucket.org");
+                }
+                fetcher @ (FetcherFunction::FetchFromGitHub
+                | FetcherFunction::FetchFromGitLab
+                | FetcherFunction::FetchFromGitea
+                | FetcherFunction::FetchFromSourcehut) => {
+                    bail!("{fetcher:?} does not support URLs without a host");
+                }
+                FetcherFunction::FetchFromGitiles => FetchFromGitiles.into(),
+                FetcherFunction::FetchFromRepoOrCz => {
+                    bail!("fetchFromRepoOrCz only supports repo.or.cz");
+                }
+                FetcherFunction::FetchHex => {
+                    bail!("fetchHex only supports hex.pm");
+                }
+                FetcherFunction::FetchPypi => {
+                    bail!("fetchPypi only supports pypi.org");
+                }
+                FetcherFunction::Fetchgit => Fetchgit(GitScheme::No).into(),
+                FetcherFunction::Fetchhg => Fetchhg(false).into(),
+                FetcherFunction::Fetchpatch => Fetchpatch(forge).into(),
+                FetcherFunction::Fetchpatch2 => Fetchpatch2(forge).into(),
+                FetcherFunction::Fetchsvn => Fetchsvn.into(),
+                FetcherFunction::Fetchurl => Fetchurl.into(),
+                FetcherFunction::Fetchzip => Fetchzip.into(),
+            }
+        }
+    })
+}
+
+fn is_archive(path: &str) -> bool {
+    let mut exts = path.rsplit('.');
+    match exts.next() {
+        Some("tar" | "tbz" | "tbz2" | "tgz" | "txz" | "zip") => true,
+        Some(_) if exts.next() == Some("tar") => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,563 @@
+use std::{
+    fmt::{self, Display, Formatter},
+    process::Command,
+    str::{self, FromStr},
+};
+
+use eyre::{Result, WrapErr, bail, eyre};
+
+use crate::credentials;
+
+/// A ref advertised by a git server
+#[derive(Debug, PartialEq, Eq)]
+pub struct Ref {
+    pub name: String,
+    pub oid: String,
+    /// The ref that a symbolic ref like `HEAD` points to
+    pub target: Option<String>,
+    /// The commit that an annotated tag points to
+    pub peeled: Option<String>,
+}
+
+/// What a revision that is not a commit hash refers to
+#[derive(Debug, PartialEq, Eq)]
+pub enum RefKind {
+    Tag,
+    /// A branch, and the commit it currently points to
+    Branch(String),
+    /// Neither a tag nor a branch
+    Unknown,
+}
+
+impl Display for RefKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            RefKind::Tag => write!(f, "tag"),
+            RefKind::Branch(commit) => write!(f, "branch {commit}"),
+            RefKind::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+impl FromStr for RefKind {
+    type Err = eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(' ') {
+            None if s == "tag" => Ok(RefKind::Tag),
+            None if s == "unknown" => Ok(RefKind::Unknown),
+            Some(("branch", commit)) => Ok(RefKind::Branch(commit.into())),
+            _ => bail!("invalid ref kind: {s}"),
+        }
+    }
+}
+
+/// Whether refs of the git repository at `url` can be listed without `git`
+fn is_http(url: &str) -> bool {
+    let url = url.strip_prefix("git+").unwrap_or(url);
+    url.starts_with("https://") || url.starts_with("http://")
+}
+
+/// Look up `rev` as a tag and as a branch, where tags take precedence like they do in git
+pub fn resolve_ref(url: &str, rev: &str) -> Result<RefKind> {
+    let names = if rev.starts_with("refs/") {
+        vec![rev.to_owned()]
+    } else {
+        vec![format!("refs/tags/{rev}"), format!("refs/heads/{rev}")]
+    };
+    let refs = ls_refs(url, &names.iter().map(String::as_str).collect::<Vec<_>>())?;
+
+    for name in names {
+        if let Some(r) = refs.iter().find(|r| r.name == name) {
+            return Ok(if name.starts_with("refs/heads/") {
+                RefKind::Branch(r.oid.clone())
+            } else {
+                RefKind::Tag
+            });
+        }
+    }
+
+    Ok(RefKind::Unknown)
+}
+
+/// An error for `rev` not being a tag or a branch, suggesting refs with similar names
+pub fn not_found(url: &str, rev: &str) -> eyre::Report {
+    let Ok(refs) = ls_refs(url, &["refs/heads/", "refs/tags/"]) else {
+        return eyre!("ref {rev} not found in {url}");
+    };
+
+    let mut similar: Vec<_> = refs
+        .iter()
+        .filter_map(|r| {
+            let name = if rev.starts_with("refs/") {
+                &r.name
+            } else {
+                r.name
+                    .strip_prefix("refs/heads/")
+                    .or_else(|| r.name.strip_prefix("refs/tags/"))?
+            };
+            let score = strsim::jaro_winkler(rev, name);
+            (score > 0.8).then_some((score, name))
+        })
+        .collect();
+    similar.sort_unstable_by(|(x, _), (y, _)| y.total_cmp(x));
+    let mut names: Vec<_> = similar.into_iter().map(|(_, name)| name).collect();
+    names.dedup();
+    names.truncate(5);
+
+    if names.is_empty() {
+        eyre!("ref {rev} not found in {url}")
+    } else {
+        eyre!(
+            "ref {rev} not found in {url}, did you mean {}?",
+            names.join(", ")
+        )
+    }
+}
+
+/// The commit that the abbreviated object name `short` refers to,
+/// if it is the tip of an advertised branch or tag, peeling annotated tags
+pub fn expand_commit(url: &str, short: &str) -> Result<Option<String>> {
+    let mut commits: Vec<_> = ls_refs(url, &["HEAD", "refs/heads/", "refs/tags/"])?
+        .into_iter()
+        .filter(|r| {
+            r.oid.starts_with(short) || r.peeled.as_ref().is_some_and(|c| c.starts_with(short))
+        })
+        .map(|r| r.peeled.unwrap_or(r.oid))
+        .collect();
+    commits.sort_unstable();
+    commits.dedup();
+
+    match commits.len() {
+        0 => Ok(None),
+        1 => Ok(commits.pop()),
+        _ => bail!("{short} is ambiguous in {url}"),
+    }
+}
+
+/// List refs of the git repository at `url` starting with any of `prefixes`
+/// over the smart HTTP protocol, using protocol v2 `ls-refs` when the server supports it,
+/// or with `git ls-remote` for other transports
+pub fn ls_refs(url: &str, prefixes: &[&str]) -> Result<Vec<Ref>> {
+    if !is_http(url) {
+        let url = url.strip_prefix("git+").unwrap_or(url);
+        return ls_remote(url, prefixes).wrap_err_with(|| format!("failed to list refs of {url}"));
+    }
+    let url = url
+        .strip_prefix("git+")
+        .unwrap_or(url)
+        .trim_end_matches('/');
+
+    (|| {
+        let mut req = ureq::get(&format!("{url}/info/refs?service=git-upload-pack"))
+            .header("Git-Protocol", "version=2");
+        if let Some(authorization) = credentials::authorization(url) {
+            req = req.header("Authorization", &authorization);
+        }
+        let advertisement = req.call()?.into_body().read_to_vec()?;
+
+        let mut pkts = parse_pkts(&advertisement)?;
+        // the service announcement is only sent by smart HTTP servers
+        if let Some(Pkt::Data(line)) = pkts.first()
+            && line.starts_with(b"# service=")
+        {
+            pkts.drain(.. 2);
+        }
+
+        match pkts.first() {
+            Some(Pkt::Data(b"version 2\n")) => ls_refs_v2(url, prefixes),
+            _ => parse_v0(&pkts, prefixes),
+        }
+    })()
+    .wrap_err_with(|| format!("failed to list refs of {url}"))
+}
+
+/// The commit `HEAD` of the git repository at `url` points to
+pub fn head(url: &str) -> Result<String> {
+    ls_refs(url, &["HEAD"])?
+        .into_iter()
+        .find(|r| r.name == "HEAD")
+        .map(|r| r.oid)
+        .ok_or_else(|| eyre!("{url} has no HEAD, the repository might be empty"))
+}
+
+fn ls_refs_v2(url: &str, prefixes: &[&str]) -> Result<Vec<Ref>> {
+    let mut req = Vec::new();
+    write_pkt(&mut req, "command=ls-refs\n");
+    req.extend(b"0001");
+    write_pkt(&mut req, "peel\n");
+    write_pkt(&mut req, "symrefs\n");
+    for prefix in prefixes {
+        write_pkt(&mut req, &format!("ref-prefix {prefix}\n"));
+    }
+    req.extend(b"0000");
+
+    let mut post = ureq::post(&format!("{url}/git-upload-pack"))
+        .header("Git-Protocol", "version=2")
+        .header("Content-Type", "application/x-git-upload-pack-request")
+        .header("Accept", "application/x-git-upload-pack-result");
+    if let Some(authorization) = credentials::authorization(url) {
+        post = post.header("Authorization", &authorization);
+    }
+    let resp = post.send(&req[..])?.into_body().read_to_vec()?;
+
+    let mut refs = Vec::new();
+    for pkt in parse_pkts(&resp)? {
+        let Pkt::Data(line) = pkt else {
+            break;
+        };
+
+        // <oid> <name> [symref-target:<target>] [peeled:<oid>]
+        let mut words = str::from_utf8(line)?.trim_end_matches('\n').split(' ');
+        let (Some(oid), Some(name)) = (words.next(), words.next()) else {
+            bail!("invalid ls-refs response");
+        };
+        let mut r = Ref {
+            name: name.into(),
+            oid: oid.into(),
+            target: None,
+            peeled: None,
+        };
+        for attr in words {
+            if let Some(target) = attr.strip_prefix("symref-target:") {
+                r.target = Some(target.into());
+            } else if let Some(peeled) = attr.strip_prefix("peeled:") {
+                r.peeled = Some(peeled.into());
+            }
+        }
+        refs.push(r);
+    }
+
+    Ok(refs)
+}
+
+// servers that do not support protocol v2 advertise all refs directly
+fn parse_v0(pkts: &[Pkt], prefixes: &[&str]) -> Result<Vec<Ref>> {
+    let mut refs: Vec<Ref> = Vec::new();
+    let mut symrefs = Vec::new();
+
+    for pkt in pkts {
+        let Pkt::Data(line) = pkt else {
+            break;
+        };
+
+        // the first line also has capabilities after a NUL byte
+        let line = str::from_utf8(line)?.trim_end_matches('\n');
+        let line =
+            match line.split_once('\0') {
+                Some((line, capabilities)) => {
+                    symrefs.extend(capabilities.split(' ').filter_map(|capability| {
+                        capability.strip_prefix("symref=")?.split_once(':')
+                    }));
+                    line
+                }
+                None => line,
+            };
+
+        let Some((oid, name)) = line.split_once(' ') else {
+            bail!("invalid ref advertisement");
+        };
+        if let Some(name) = name.strip_suffix("^{}") {
+            if let Some(r) = refs.iter_mut().rfind(|r| r.name == name) {
+                r.peeled = Some(oid.into());
+            }
+        } else if name != "capabilities^{}" {
+            refs.push(Ref {
+                name: name.into(),
+                oid: oid.into(),
+                target: None,
+                peeled: None,
+            });
+        }
+    }
+
+    for r in &mut refs {
+        r.target = symrefs
+            .iter()
+            .find(|(symref, _)| *symref == r.name)
+            .map(|(_, target)| (*target).into());
+    }
+    refs.retain(|r| prefixes.iter().any(|prefix| r.name.starts_with(prefix)));
+
+    Ok(refs)
+}
+
+fn ls_remote(url: &str, prefixes: &[&str]) -> Result<Vec<Ref>> {
+    let output = Command::new("git")
+        .args(["ls-remote", "--symref", "--", url])
+        .envs(credentials::git_env(url))
+        .output()?;
+    if !output.status.success() {
+        bail!(
+            "git ls-remote exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim(),
+        );
+    }
+
+    let mut refs: Vec<Ref> = Vec::new();
+    let mut symrefs = Vec::new();
+    for line in str::from_utf8(&output.stdout)?.lines() {
+        let Some((oid, name)) = line.split_once('\t') else {
+            bail!("invalid git ls-remote output");
+        };
+
+        if let Some(target) = oid.strip_prefix("ref: ") {
+            symrefs.push((name, target));
+        } else if let Some(name) = name.strip_suffix("^{}") {
+            if let Some(r) = refs.iter_mut().rfind(|r| r.name == name) {
+                r.peeled = Some(oid.into());
+            }
+        } else {
+            refs.push(Ref {
+                name: name.into(),
+                oid: oid.into(),
+                target: None,
+                peeled: None,
+            });
+        }
+    }
+
+    for r in &mut refs {
+        r.target = symrefs
+            .iter()
+            .find(|(symref, _)| *symref == r.name)
+            .map(|(_, target)| (*target).into());
+    }
+    refs.retain(|r| prefixes.iter().any(|prefix| r.name.starts_with(prefix)));
+
+    Ok(refs)
+}
+
+#[derive(Debug)]
+enum Pkt<'a> {
+    Flush,
+    Delim,
+    ResponseEnd,
+    Data(&'a [u8]),
+}
+
+fn parse_pkts(mut buf: &[u8]) -> Result<Vec<Pkt<'_>>> {
+    let mut pkts = Vec::new();
+
+    while !buf.is_empty() {
+        let len = buf
+            .get(.. 4)
+            .and_then(|len| usize::from_str_radix(str::from_utf8(len).ok()?, 16).ok())
+            .ok_or_else(|| eyre!("invalid pkt-line"))?;
+
+        pkts.push(match len {
+            0 => Pkt::Flush,
+            1 => Pkt::Delim,
+            2 => Pkt::ResponseEnd,
+            3 => bail!("invalid pkt-line length"),
+            _ => Pkt::Data(
+                buf.get(4 .. len)
+                    .ok_or_else(|| eyre!("truncated pkt-line"))?,
+            ),
+        });
+        buf = &buf[len.max(4) ..];
+    }
+
+    Ok(pkts)
+}
+
+fn write_pkt(buf: &mut Vec<u8>, data: &str) {
+    buf.extend(format!("{:04x}", data.len() + 4).as_bytes());
+    buf.extend(data.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        path::Path,
+        process::{Command, Stdio},
+        thread,
+    };
+
+    use tempfile::TempDir;
+
+    use super::{Ref, RefKind, head, ls_refs, not_found, resolve_ref};
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_AUTHOR_NAME", "nurl")
+            .env("GIT_AUTHOR_EMAIL", "nurl@example.org")
+            .env("GIT_COMMITTER_NAME", "nurl")
+            .env("GIT_COMMITTER_EMAIL", "nurl@example.org")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?}");
+        String::from_utf8(output.stdout).unwrap().trim().into()
+    }
+
+    // serve `git http-backend` as CGI, dropping the Git-Protocol header unless `v2` is true
+    fn serve(root: &Path, v2: bool) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let root = root.to_owned();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(&stream);
+
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                let mut words = line.split(' ');
+                let method = words.next().unwrap().to_owned();
+                let target = words.next().unwrap();
+                let (path, query) = target.split_once('?').unwrap_or((target, ""));
+                let (path, query) = (path.to_owned(), query.to_owned());
+
+                let mut content_type = String::new();
+                let mut content_length = 0;
+                let mut protocol = String::new();
+                loop {
+                    line.clear();
+                    reader.read_line(&mut line).unwrap();
+                    let Some((key, value)) = line.trim_end().split_once(": ") else {
+                        break;
+                    };
+                    match key.to_ascii_lowercase().as_str() {
+                        "content-type" => content_type = value.into(),
+                        "content-length" => content_length = value.parse().unwrap(),
+                        "git-protocol" if v2 => protocol = value.into(),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+
+                let mut backend = Command::new("git")
+                    .arg("http-backend")
+                    .env("GIT_PROJECT_ROOT", &root)
+                    .env("GIT_HTTP_EXPORT_ALL", "1")
+                    .env("GIT_PROTOCOL", protocol)
+                    .env("REQUEST_METHOD", method)
+                    .env("PATH_INFO", path)
+                    .env("QUERY_STRING", query)
+                    .env("CONTENT_TYPE", content_type)
+                    .env("CONTENT_LENGTH", content_length.to_string())
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .unwrap();
+                backend.stdin.take().unwrap().write_all(&body).unwrap();
+                let output = backend.wait_with_output().unwrap().stdout;
+
+                let split = output.windows(4).position(|x| x == b"\r\n\r\n").unwrap();
+                let headers = String::from_utf8_lossy(&output[.. split]);
+                let status = headers
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Status: "))
+                    .unwrap_or("200 OK");
+                let body = &output[split + 4 ..];
+
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\n{headers}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len(),
+                )
+                .unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+
+        format!("http://{addr}/repo.git")
+    }
+
+    #[test]
+    fn smart_http() {
+        let dir = TempDir::new().unwrap();
+        let work = dir.path().join("work");
+        let bare = dir.path().join("repo.git");
+
+        git(dir.path(), &["init", "-q", "-b", "main", "work"]);
+        git(&work, &["commit", "-q", "--allow-empty", "-m", "first"]);
+        let first = git(&work, &["rev-parse", "HEAD"]);
+        git(&work, &["tag", "v1.0.0"]);
+        git(&work, &["commit", "-q", "--allow-empty", "-m", "second"]);
+        let second = git(&work, &["rev-parse", "HEAD"]);
+        git(&work, &["tag", "-a", "-m", "annotated", "v2.0.0"]);
+        let annotated = git(&work, &["rev-parse", "v2.0.0"]);
+        git(&work, &["branch", "dev", &first]);
+        git(
+            dir.path(),
+            &["clone", "-q", "--bare", "work", bare.to_str().unwrap()],
+        );
+
+        let local = bare.to_str().unwrap().to_owned();
+        for (url, v2) in [
+            (serve(dir.path(), true), true),
+            (serve(dir.path(), false), false),
+            (local, false),
+        ] {
+            assert_eq!(head(&url).unwrap(), second);
+            assert_eq!(
+                resolve_ref(&url, "dev").unwrap(),
+                RefKind::Branch(first.clone())
+            );
+            assert_eq!(
+                resolve_ref(&url, "refs/heads/main").unwrap(),
+                RefKind::Branch(second.clone()),
+            );
+            assert_eq!(resolve_ref(&url, "v2.0.0").unwrap(), RefKind::Tag);
+            assert_eq!(resolve_ref(&url, "missing").unwrap(), RefKind::Unknown);
+
+            assert_eq!(resolve_ref(&url, "refs/tags/v2.0.0").unwrap(), RefKind::Tag);
+            assert_eq!(
+                not_found(&url, "v2.0").to_string(),
+                format!("ref v2.0 not found in {url}, did you mean v2.0.0?"),
+            );
+            assert_eq!(
+                not_found(&url, "xyz").to_string(),
+                format!("ref xyz not found in {url}"),
+            );
+
+            let mut refs = ls_refs(&url, &["HEAD", "refs/heads/", "refs/tags/"]).unwrap();
+            refs.sort_unstable_by(|x, y| x.name.cmp(&y.name));
+            assert_eq!(
+                refs,
+                [
+                    Ref {
+                        name: "HEAD".into(),
+                        oid: second.clone(),
+                        target: Some("refs/heads/main".into()),
+                        peeled: None,
+                    },
+                    Ref {
+                        name: "refs/heads/dev".into(),
+                        oid: first.clone(),
+                        target: None,
+                        peeled: None,
+                    },
+                    Ref {
+                        name: "refs/heads/main".into(),
+                        oid: second.clone(),
+                        target: None,
+                        peeled: None,
+                    },
+                    Ref {
+                        name: "refs/tags/v1.0.0".into(),
+                        oid: first.clone(),
+                        target: None,
+                        peeled: None,
+                    },
+                    Ref {
+                        name: "refs/tags/v2.0.0".into(),
+                        oid: annotated.clone(),
+                        target: None,
+                        peeled: Some(second.clone()),
+                    },
+                ],
+                "v2: {v2}",
+            );
+        }
+    }
+}
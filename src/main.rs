@@ -1,76 +1,63 @@
-mod cli;
-mod config;
-mod fetcher;
-mod prefetch;
-mod revless;
-mod simple;
-
 use std::{
-    fmt::{self, Display, Formatter},
-    io::{IsTerminal, Write, stdout},
-    str::Split,
+    fs::File,
+    io::{BufReader, IsTerminal, Write, stdin, stdout},
+    time::Duration,
 };
 
 use bstr::ByteSlice;
-use clap::{Parser, ValueEnum};
-use eyre::{Result, bail};
-use gix_url::Scheme;
-use supports_color::Stream;
-
-use crate::{
-    cli::{FetcherFunction, Opts},
-    config::FetcherConfig,
-    fetcher::{
-        BuiltinsFetchGit, FetchCrate, FetchFromBitbucket, FetchFromGitHub, FetchFromGitLab,
-        FetchFromGitea, FetchFromGitiles, FetchFromRepoOrCz, FetchFromSourcehut, FetchHex,
-        FetchPypi, Fetcher, FetcherDispatch, Fetchgit, Fetchhg, Fetchpatch, Fetchpatch2, Fetchsvn,
-        Fetchurl, Fetchzip,
-    },
-    prefetch::fod_prefetch,
+use clap::{CommandFactory, FromArgMatches, ValueEnum};
+use eyre::{OptionExt, Result, bail};
+use nurl::{
+    Fetcher, FetcherConfig, Url, batch,
+    cache::Cache,
+    cli::{CacheCommand, Command, FetcherFunction, Opts},
+    infer_fetcher,
+    prefetch::Source,
+    settings::Settings,
+    split_url,
+    update::{self, Selection},
+    url_path,
 };
-
-pub struct Url<'a> {
-    url: &'a str,
-    path: &'a str,
-}
-
-impl Url<'_> {
-    fn as_str(&self) -> &str {
-        self.url
-    }
-
-    fn path_segments(&self) -> Split<'_, char> {
-        self.path.split('/')
-    }
-}
-
-impl Display for Url<'_> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.write_str(self.as_str())
-    }
-}
-
-pub enum GitScheme {
-    Yes,
-    No,
-    Plus,
-}
+use supports_color::Stream;
 
 fn main() -> Result<()> {
     if supports_color::on(Stream::Stderr).is_some() {
         color_eyre::install()?;
     }
 
-    let opts = Opts::parse();
+    let matches = Opts::command().get_matches();
+    let mut opts = Opts::from_arg_matches(&matches)?;
+    let settings = Settings::load()?;
+    settings.apply(&mut opts, &matches)?;
+    settings.configure_credentials();
+    let inference = settings.inference(&opts)?;
     let out = &mut stdout().lock();
 
-    if let Some(expr) = opts.expr {
+    if let Some(Command::Cache(cmd)) = opts.subcommand {
+        let cache = Cache::new(false).ok_or_eyre("failed to find the cache directory")?;
+        match cmd {
+            CacheCommand::List => {
+                for entry in cache.entries()? {
+                    writeln!(out, "{}\t{}", entry.hash, entry.key)?;
+                }
+            }
+            CacheCommand::Gc { max_age } => {
+                let removed = cache.gc(Duration::from_secs(max_age * 24 * 60 * 60))?;
+                writeln!(out, "removed {removed} cached hash(es)")?;
+            }
+        }
+
+        return Ok(());
+    }
+
+    if let Some(expr) = opts.expr.take() {
+        let cfg = FetcherConfig::try_from(opts)?;
         write!(
             out,
             "{}",
-            fod_prefetch(format!(
+            cfg.prefetch(Source::Fod(format!(
                 r#"({expr}).overrideAttrs(_:{{outputHash="";outputHashAlgo="sha256";}})"#,
-            ))?
+            )))?
         )?;
 
         if out.is_terminal() {
@@ -105,183 +92,64 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
-    let url: gix_url::Url = opts.url.as_str().try_into()?;
-    let path = url.path.to_str()?;
-    let path = path.strip_prefix('/').unwrap_or(path);
-    let path = path.split_once('?').map_or(path, |(path, _)| path);
-
-    let fetcher: FetcherDispatch = match (opts.fetcher, url.host(), &url.scheme) {
-        // high priority
-
-        // prefer fetchpatch over fetchpatch2: https://github.com/NixOS/nixpkgs/issues/257446
-        (None, ..) if path.ends_with(".diff") || path.ends_with(".patch") => Fetchpatch.into(),
-
-        (None, ..) if is_archive(path) => Fetchzip.into(),
-
-        // low priority
-        (Some(FetcherFunction::BuiltinsFetchGit), ..) => BuiltinsFetchGit.into(),
-
-        (None | Some(FetcherFunction::FetchCrate), Some("crates.io"), _) => FetchCrate(true).into(),
-        (None | Some(FetcherFunction::FetchCrate), Some("lib.rs"), _) => FetchCrate(false).into(),
-        (Some(FetcherFunction::FetchCrate), ..) => {
-            bail!("fetchCrate only supports crates.io and lib.rs");
-        }
-
-        (None | Some(FetcherFunction::FetchFromBitbucket), Some("bitbucket.org"), _) => {
-            FetchFromBitbucket.into()
-        }
-        (Some(FetcherFunction::FetchFromBitbucket), ..) => {
-            bail!("fetchFromBitbucket only supports bitbucket.org");
-        }
-
-        (None | Some(FetcherFunction::FetchFromGitHub), Some("github.com"), _) => {
-            FetchFromGitHub(None).into()
-        }
-        (Some(FetcherFunction::FetchFromGitHub), Some(host), _) => {
-            FetchFromGitHub(Some(host)).into()
-        }
-
-        (None | Some(FetcherFunction::FetchFromGitLab), Some("gitlab.com"), _) => {
-            FetchFromGitLab::new(None).into()
-        }
-        (None, Some(host), _) if host.starts_with("gitlab.") => {
-            FetchFromGitLab::new(Some(host)).into()
-        }
-        (None, Some(host @ ("framagit.org" | "invent.kde.org" | "salsa.debian.org")), _) => {
-            FetchFromGitLab::new(Some(host)).into()
-        }
-        (Some(FetcherFunction::FetchFromGitLab), Some(host), _) => {
-            FetchFromGitLab::new(Some(host)).into()
-        }
-
-        (
-            None | Some(FetcherFunction::FetchFromGitea),
-            Some(
-                host @ ("codeberg.org" | "git.lix.systems" | "gitea.com" | "notabug.org"
-                | "repo.palemoon.org"),
-            ),
-            _,
-        ) => FetchFromGitea(host).into(),
-        (Some(FetcherFunction::FetchFromGitea), Some(host), _) => FetchFromGitea(host).into(),
-
-        (None | Some(FetcherFunction::FetchFromGitiles), Some(host), _)
-            if host.ends_with(".googlesource.com") =>
-        {
-            FetchFromGitiles.into()
-        }
-        (Some(FetcherFunction::FetchFromGitiles), ..) => FetchFromGitiles.into(),
-
-        (None | Some(FetcherFunction::FetchFromRepoOrCz), Some("repo.or.cz"), _) => {
-            FetchFromRepoOrCz.into()
-        }
-        (Some(FetcherFunction::FetchFromRepoOrCz), ..) => {
-            bail!("fetchFromRepoOrCz only supports repo.or.cz");
-        }
-
-        (None | Some(FetcherFunction::FetchFromSourcehut), Some("git.sr.ht"), _) => {
-            FetchFromSourcehut(None).into()
-        }
-        (Some(FetcherFunction::FetchFromSourcehut), Some(host), _) => {
-            FetchFromSourcehut(Some(host)).into()
-        }
-
-        (
-            Some(
-                fetcher @ (FetcherFunction::FetchFromGitHub
-                | FetcherFunction::FetchFromGitLab
-                | FetcherFunction::FetchFromGitea
-                | FetcherFunction::FetchFromSourcehut),
-            ),
-            None,
-            _,
-        ) => {
-            bail!("{fetcher:?} does not support URLs without a host");
-        }
-
-        (None | Some(FetcherFunction::FetchHex), Some("hex.pm"), _) => FetchHex.into(),
-        (Some(FetcherFunction::FetchHex), ..) => {
-            bail!("fetchHex only supports hex.pm");
-        }
+    if let Some(path) = opts.batch.take() {
+        let cfg = opts.try_into()?;
+        let failures = if path == "-" {
+            batch::run(stdin().lock(), out, &inference, &cfg)?
+        } else {
+            batch::run(BufReader::new(File::open(path)?), out, &inference, &cfg)?
+        };
 
-        (None | Some(FetcherFunction::FetchPypi), Some("pypi.org"), _) => FetchPypi.into(),
-        (Some(FetcherFunction::FetchPypi), ..) => {
-            bail!("fetchPypi only supports pypi.org");
+        if failures != 0 {
+            bail!("failed to prefetch {failures} item(s)");
         }
 
-        (None | Some(FetcherFunction::Fetchgit), _, Scheme::Git) => Fetchgit(GitScheme::Yes).into(),
-        (None | Some(FetcherFunction::Fetchgit), _, Scheme::Ext(scheme))
-            if scheme.starts_with("git+") =>
-        {
-            Fetchgit(GitScheme::Plus).into()
-        }
-        (Some(FetcherFunction::Fetchgit), ..) => Fetchgit(GitScheme::No).into(),
+        return Ok(());
+    }
 
-        (None | Some(FetcherFunction::Fetchhg), _, Scheme::Ext(scheme))
-            if scheme.starts_with("hg+") =>
-        {
-            Fetchhg(true).into()
+    if let Some(path) = opts.update.take() {
+        let selection = if let Some(line) = opts.update_line {
+            Selection::Line(line)
+        } else if let Some(attr) = opts.update_attr.take() {
+            Selection::Attr(attr)
+        } else {
+            Selection::All
+        };
+        let rev = opts.update_rev.take();
+
+        let failures = update::run(
+            &path,
+            &selection,
+            rev.as_deref(),
+            &inference,
+            &opts.try_into()?,
+        )?;
+        if failures != 0 {
+            bail!("failed to update {failures} fetcher call(s)");
         }
-        (Some(FetcherFunction::Fetchhg), ..) => Fetchhg(false).into(),
-
-        (Some(FetcherFunction::Fetchpatch), ..) => Fetchpatch.into(),
 
-        (Some(FetcherFunction::Fetchpatch2), ..) => Fetchpatch2.into(),
-
-        (None, _, Scheme::Ext(scheme)) if scheme == "svn" => Fetchsvn.into(),
-        (Some(FetcherFunction::Fetchsvn), ..) => Fetchsvn.into(),
-
-        (Some(FetcherFunction::Fetchurl), ..) => Fetchurl.into(),
+        return Ok(());
+    }
 
-        (Some(FetcherFunction::Fetchzip), ..) => Fetchzip.into(),
+    let (url, url_rev) = split_url(&opts.url, opts.fetcher, &inference);
+    opts.rev = opts.rev.or(url_rev.rev);
+    opts.submodules = opts.submodules.or(url_rev.submodules);
 
-        (None, ..) => match opts.fallback {
-            FetcherFunction::BuiltinsFetchGit => BuiltinsFetchGit.into(),
-            FetcherFunction::FetchCrate => {
-                bail!("fetchCrate only supports crates.io and lib.rs");
-            }
-            FetcherFunction::FetchFromBitbucket => {
-                bail!("fetchFromBitbucket only supports bitbucket.org");
-            }
-            fetcher @ (FetcherFunction::FetchFromGitHub
-            | FetcherFunction::FetchFromGitLab
-            | FetcherFunction::FetchFromGitea
-            | FetcherFunction::FetchFromSourcehut) => {
-                bail!("{fetcher:?} does not support URLs without a host");
-            }
-            FetcherFunction::FetchFromGitiles => FetchFromGitiles.into(),
-            FetcherFunction::FetchFromRepoOrCz => {
-                bail!("fetchFromRepoOrCz only supports repo.or.cz");
-            }
-            FetcherFunction::FetchHex => {
-                bail!("fetchHex only supports hex.pm");
-            }
-            FetcherFunction::FetchPypi => {
-                bail!("fetchPypi only supports pypi.org");
-            }
-            FetcherFunction::Fetchgit => Fetchgit(GitScheme::No).into(),
-            FetcherFunction::Fetchhg => Fetchhg(false).into(),
-            FetcherFunction::Fetchpatch => Fetchpatch.into(),
-            FetcherFunction::Fetchpatch2 => Fetchpatch2.into(),
-            FetcherFunction::Fetchsvn => Fetchsvn.into(),
-            FetcherFunction::Fetchurl => Fetchurl.into(),
-            FetcherFunction::Fetchzip => Fetchzip.into(),
-        },
-    };
+    let url: gix_url::Url = url.as_str().try_into()?;
+    let path = url_path(&url)?;
+    let fetcher = infer_fetcher(&url, path, opts.fetcher, &inference)?;
 
     let url_bstring = url.to_bstring();
-    let url = Url {
-        url: url_bstring.to_str()?,
-        path,
-    };
+    let url = Url::new(url_bstring.to_str()?, path);
 
     if opts.hash {
-        fetcher.fetch_hash(out, &url, opts.into())?;
+        fetcher.fetch_hash(out, &url, opts.try_into()?)?;
     } else if opts.json {
-        fetcher.fetch_json(out, &url, opts.into())?;
+        fetcher.fetch_json(out, &url, opts.try_into()?)?;
     } else if opts.parse {
         fetcher.to_json(out, &url, opts.rev)?;
     } else {
-        let mut cfg = FetcherConfig::from(opts);
+        let mut cfg = FetcherConfig::try_from(opts)?;
         cfg.merge_overwrites();
         fetcher.fetch_nix(out, &url, cfg)?;
     }
@@ -292,12 +160,3 @@ fn main() -> Result<()> {
 
     Ok(())
 }
-
-fn is_archive(path: &str) -> bool {
-    let mut exts = path.rsplit('.');
-    match exts.next() {
-        Some("tar" | "tbz" | "tbz2" | "tgz" | "txz" | "zip") => true,
-        Some(_) if exts.next() == Some("tar") => true,
-        _ => false,
-    }
-}
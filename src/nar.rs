@@ -0,0 +1,169 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+    path::Path,
+};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use sha2::{Digest, Sha256};
+
+/// Serialize the file system object at `path` into a Nix archive,
+/// skipping directory entries matched by `exclude`
+///
+/// The contents of files are streamed, so the tree is never held in memory
+pub fn dump_path(
+    path: &Path,
+    exclude: &dyn Fn(&[u8]) -> bool,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    write_str(out, b"nix-archive-1")?;
+    dump_node(path, exclude, out)
+}
+
+/// The SRI hash of the Nix archive of `path`, i.e. what `nix hash path` outputs
+pub fn hash_path(path: &Path, exclude: &dyn Fn(&[u8]) -> bool) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    dump_path(path, exclude, &mut HashWriter(&mut hasher))?;
+    Ok(sri(hasher))
+}
+
+fn dump_node(path: &Path, exclude: &dyn Fn(&[u8]) -> bool, out: &mut impl Write) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+
+    write_str(out, b"(")?;
+    write_str(out, b"type")?;
+
+    if metadata.is_symlink() {
+        write_str(out, b"symlink")?;
+        write_str(out, b"target")?;
+        write_str(out, fs::read_link(path)?.as_os_str().as_bytes())?;
+    } else if metadata.is_dir() {
+        write_str(out, b"directory")?;
+
+        let mut names = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let name = entry?.file_name();
+            if !exclude(name.as_bytes()) {
+                names.push(name);
+            }
+        }
+        names.sort_unstable_by(|x, y| x.as_bytes().cmp(y.as_bytes()));
+
+        for name in names {
+            write_str(out, b"entry")?;
+            write_str(out, b"(")?;
+            write_str(out, b"name")?;
+            write_str(out, name.as_bytes())?;
+            write_str(out, b"node")?;
+            dump_node(&path.join(name), exclude, out)?;
+            write_str(out, b")")?;
+        }
+    } else {
+        write_str(out, b"regular")?;
+        if metadata.permissions().mode() & 0o100 != 0 {
+            write_str(out, b"executable")?;
+            write_str(out, b"")?;
+        }
+        write_str(out, b"contents")?;
+
+        let len = metadata.len();
+        out.write_all(&len.to_le_bytes())?;
+        if io::copy(&mut File::open(path)?, out)? != len {
+            return Err(io::Error::other(format!(
+                "{} changed while reading it",
+                path.display(),
+            )));
+        }
+        write_padding(out, len)?;
+    }
+
+    write_str(out, b")")
+}
+
+fn write_str(out: &mut impl Write, s: &[u8]) -> io::Result<()> {
+    out.write_all(&(s.len() as u64).to_le_bytes())?;
+    out.write_all(s)?;
+    write_padding(out, s.len() as u64)
+}
+
+fn write_padding(out: &mut impl Write, len: u64) -> io::Result<()> {
+    out.write_all(&[0; 8][.. (8 - len % 8) as usize % 8])
+}
+
+/// Adapts a hasher to [`Write`], so data can be hashed with [`io::copy`]
+pub struct HashWriter<'a>(pub &'a mut Sha256);
+
+impl Write for HashWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn sri(hasher: Sha256) -> String {
+    format!("sha256-{}", BASE64_STANDARD.encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, Permissions},
+        os::unix::fs::{PermissionsExt, symlink},
+    };
+
+    use tempfile::TempDir;
+
+    use super::{dump_path, hash_path};
+
+    #[test]
+    fn file() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("hello");
+        fs::write(&path, "hello\n").unwrap();
+
+        let mut nar = Vec::new();
+        dump_path(&path, &|_| false, &mut nar).unwrap();
+
+        let mut expected = Vec::new();
+        for s in [
+            &b"nix-archive-1"[..],
+            b"(",
+            b"type",
+            b"regular",
+            b"contents",
+            b"hello\n",
+            b")",
+        ] {
+            expected.extend((s.len() as u64).to_le_bytes());
+            expected.extend(s);
+            expected.resize(expected.len().next_multiple_of(8), 0);
+        }
+
+        assert_eq!(nar, expected);
+    }
+
+    #[test]
+    fn directory() {
+        let dir = TempDir::new().unwrap();
+        let bin = dir.path().join("bin");
+        fs::create_dir(&bin).unwrap();
+        fs::write(bin.join("hello"), "#!/bin/sh\necho hello\n").unwrap();
+        fs::set_permissions(bin.join("hello"), Permissions::from_mode(0o755)).unwrap();
+        symlink("bin/hello", dir.path().join("link")).unwrap();
+
+        let hash = "sha256-KMUk9cUsK+RHKZ677Pxi9krSwb7acD/k29K+PxmQgcc=";
+        assert_eq!(hash_path(dir.path(), &|_| false).unwrap(), hash);
+
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        assert_ne!(hash_path(dir.path(), &|_| false).unwrap(), hash);
+        assert_eq!(
+            hash_path(dir.path(), &|name| name == b".git").unwrap(),
+            hash
+        );
+    }
+}
@@ -0,0 +1,163 @@
+use std::io::Write;
+
+use eyre::Result;
+use serde::{Serialize, Serializer, ser::SerializeMap};
+use serde_json::{Value, json};
+
+/// A fetcher call with its hash, the structured equivalent of `--json`
+pub struct FetcherOutput {
+    pub fetcher: &'static str,
+    /// Arguments in the order they appear in the generated Nix expression,
+    /// including the hash
+    pub args: Vec<(String, Arg)>,
+    /// The branch that was pinned to the commit in the revision
+    pub branch: Option<String>,
+}
+
+pub enum Arg {
+    String(String),
+    Bool(bool),
+    /// A Nix expression, from `--arg`
+    Nix(String),
+}
+
+impl FetcherOutput {
+    pub fn new(fetcher: &'static str) -> Self {
+        Self {
+            fetcher,
+            args: Vec::new(),
+            branch: None,
+        }
+    }
+
+    pub fn arg(&self, key: &str) -> Option<&Arg> {
+        self.args
+            .iter()
+            .find_map(|(k, value)| (k == key).then_some(value))
+    }
+
+    pub fn push(&mut self, key: impl Into<String>, value: Arg) {
+        self.args.push((key.into(), value));
+    }
+
+    pub fn push_str(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.push(key, Arg::String(value.into()));
+    }
+
+    /// Replace the value of `key` in place, or add it if it is not an argument yet
+    pub fn set(&mut self, key: &str, value: Arg) {
+        match self.args.iter_mut().find(|(k, _)| k == key) {
+            Some((_, old)) => *old = value,
+            None => self.push(key, value),
+        }
+    }
+
+    /// Write the fetcher call as a Nix expression, with the branch as a comment after `rev`
+    pub fn write_nix(&self, out: &mut impl Write, indent: &str) -> Result<()> {
+        writeln!(out, "{} {{", self.fetcher)?;
+
+        for (key, value) in &self.args {
+            match value {
+                Arg::String(value) => match &self.branch {
+                    Some(branch) if key == "rev" => {
+                        writeln!(out, r#"{indent}  {key} = "{value}"; # {branch}"#)?;
+                    }
+                    _ => writeln!(out, r#"{indent}  {key} = "{value}";"#)?,
+                },
+                Arg::Bool(value) => writeln!(out, "{indent}  {key} = {value};")?,
+                Arg::Nix(value) => writeln!(out, "{indent}  {key} = {value};")?,
+            }
+        }
+
+        write!(out, "{indent}}}")?;
+
+        Ok(())
+    }
+
+    /// The output of `--json`
+    pub fn to_json(&self) -> Result<Value> {
+        Ok(serde_json::to_value(self)?)
+    }
+}
+
+// keys are sorted, like the keys of every JSON object nurl outputs
+impl Serialize for FetcherOutput {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        struct Args<'a>(&'a [(String, Arg)]);
+
+        impl Serialize for Args<'_> {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                let mut args: Vec<_> = self.0.iter().collect();
+                args.sort_by(|(x, _), (y, _)| x.cmp(y));
+
+                let mut map = serializer.serialize_map(Some(args.len()))?;
+                for (key, value) in args {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("args", &Args(&self.args))?;
+        if let Some(branch) = &self.branch {
+            map.serialize_entry("branch", branch)?;
+        }
+        map.serialize_entry("fetcher", self.fetcher)?;
+        map.end()
+    }
+}
+
+impl Serialize for Arg {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Arg::String(value) => value.serialize(serializer),
+            Arg::Bool(value) => value.serialize(serializer),
+            Arg::Nix(value) => json!({
+                "type": "nix",
+                "value": value,
+            })
+            .serialize(serializer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Arg, FetcherOutput};
+
+    #[test]
+    fn serialize() {
+        let mut output = FetcherOutput::new("fetchFromGitHub");
+        output.push_str("owner", "nix-community");
+        output.push_str("repo", "nurl");
+        output.push("fetchSubmodules", Arg::Bool(true));
+        output.push("postFetch", Arg::Nix("null".into()));
+
+        assert_eq!(
+            serde_json::to_string(&output).unwrap(),
+            r#"{"args":{"fetchSubmodules":true,"owner":"nix-community","postFetch":{"type":"nix","value":"null"},"repo":"nurl"},"fetcher":"fetchFromGitHub"}"#,
+        );
+    }
+
+    #[test]
+    fn write_nix() {
+        let mut output = FetcherOutput::new("fetchgit");
+        output.push_str("url", "https://example.com/repo.git");
+        output.push_str("rev", "4d5515e112addf88b4107dd9357243dc10624e58");
+        output.push("fetchSubmodules", Arg::Bool(true));
+        output.set("url", Arg::Nix("src.url".into()));
+        output.branch = Some("main".into());
+
+        let mut out = Vec::new();
+        output.write_nix(&mut out, "  ").unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"fetchgit {
+    url = src.url;
+    rev = "4d5515e112addf88b4107dd9357243dc10624e58"; # main
+    fetchSubmodules = true;
+  }"#,
+        );
+    }
+}
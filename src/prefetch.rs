@@ -1,23 +1,97 @@
 use std::{
-    io::BufRead,
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt::{self, Display, Formatter},
+    fs,
+    io::{self, BufRead, BufReader, Read},
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
     process::{Command, Output, Stdio},
+    sync::Mutex,
+    thread,
 };
 
 use eyre::{Result, bail, eyre};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use ureq::Body;
+
+use crate::{
+    archive::{strip_root, unpack},
+    credentials,
+    fetcher::{FetchFromGitLab, PatchPage},
+    ls_refs::{self, RefKind},
+    nar::{HashWriter, hash_path, sri},
+    rev::is_commit_hash,
+};
 
 #[derive(Deserialize)]
 struct PrefetchOutput {
     hash: String,
 }
 
+thread_local! {
+    static TAG: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Run `f` with everything printed to stderr, including the output of nix, prefixed with `tag`
+pub fn with_tag<T>(tag: String, f: impl FnOnce() -> T) -> T {
+    // restores the previous tag even if `f` panics
+    struct Restore(Option<String>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            TAG.set(self.0.take());
+        }
+    }
+
+    let _restore = Restore(TAG.replace(Some(tag)));
+    f()
+}
+
+pub(crate) fn eprintln_tagged(msg: impl Display) {
+    TAG.with_borrow(|tag| match tag {
+        Some(tag) => eprintln!("[{tag}] {msg}"),
+        None => eprintln!("{msg}"),
+    });
+}
+
 trait GetStdout {
     fn get_stdout(&mut self) -> Result<Vec<u8>>;
 }
 
 impl GetStdout for Command {
     fn get_stdout(&mut self) -> Result<Vec<u8>> {
-        let Output { stdout, status, .. } = self.stderr(Stdio::inherit()).output()?;
+        if TAG.with_borrow(Option::is_none) {
+            let Output { stdout, status, .. } = self.stderr(Stdio::inherit()).output()?;
+            if !status.success() {
+                bail!("command exited with {}", status);
+            }
+            return Ok(stdout);
+        }
+
+        let mut child = self.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()?;
+        let (Some(mut stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+            unreachable!();
+        };
+
+        let stdout = thread::scope(|s| -> Result<_> {
+            let stdout = s.spawn(move || {
+                let mut buf = Vec::new();
+                stdout.read_to_end(&mut buf).map(|_| buf)
+            });
+
+            for line in BufReader::new(stderr).split(b'\n') {
+                eprintln_tagged(String::from_utf8_lossy(&line?));
+            }
+
+            Ok(stdout
+                .join()
+                .map_err(|_| eyre!("failed to read stdout"))??)
+        })?;
+
+        let status = child.wait()?;
         if !status.success() {
             bail!("command exited with {}", status);
         }
@@ -28,83 +102,423 @@ impl GetStdout for Command {
 macro_rules! info {
     ($($tt:tt)+) => {{
         use owo_colors::{OwoColorize, Stream, Style};
-        eprintln!(
-            "{}",
+        eprintln_tagged(
             format_args!($($tt)+).if_supports_color(Stream::Stderr, |text| text
                 .style(Style::new().blue().bold()))
         );
     }};
 }
 
-pub fn flake_prefetch(flake_ref: String) -> Result<String> {
-    info!(
-        "$ nix flake prefetch --extra-experimental-features 'nix-command flakes' --json {flake_ref}"
-    );
-    Ok(serde_json::from_slice::<PrefetchOutput>(
-        &Command::new("nix")
-            .arg("flake")
-            .arg("prefetch")
-            .arg("--extra-experimental-features")
-            .arg("nix-command flakes")
-            .arg("--json")
-            .arg(flake_ref)
-            .get_stdout()?,
-    )?
-    .hash)
-}
-
-// work around for https://github.com/NixOS/nix/issues/5291
-pub fn git_prefetch(git_scheme: bool, url: &str, rev: &str, submodules: bool) -> Result<String> {
-    let prefix = if git_scheme { "" } else { "git+" };
-    let submodules = if submodules { "&submodules=1" } else { "" };
-
-    if rev.len() == 40 {
-        flake_prefetch(format!("{prefix}{url}?allRefs=1&rev={rev}{submodules}"))
-    } else {
-        if !rev.starts_with("refs/")
-            && let hash @ Ok(_) =
-                flake_prefetch(format!("{prefix}{url}?ref=refs/tags/{rev}{submodules}"))
-        {
-            return hash;
+/// Something to prefetch, its [`Display`] implementation is used as the key for recorded hashes
+#[derive(Clone, Debug)]
+pub enum Source {
+    /// A flake reference, prefetched with `nix flake prefetch`
+    Flake(String),
+    /// A file that is hashed as is, like `fetchurl` does
+    File(String),
+    /// A tarball or zip file that is unpacked before hashing, like `fetchzip` does
+    Tarball(String),
+    /// A revision of a git repository, hashed without `.git` like `fetchgit` does
+    ///
+    /// `rev` is a commit hash or the full name of a ref, so it is never looked up again
+    Git {
+        url: String,
+        rev: String,
+        submodules: bool,
+    },
+    /// A fixed-output derivation, built with a fake hash to get the actual hash from the error
+    Fod(String),
+}
+
+impl Source {
+    /// A git repository at the revision written out as `rev_key`, `url` can have a `git+` prefix
+    pub fn git(url: &str, rev_key: &str, rev: &str, submodules: bool) -> Self {
+        Source::Git {
+            url: url.strip_prefix("git+").unwrap_or(url).into(),
+            rev: if rev_key == "tag" && !rev.starts_with("refs/") {
+                format!("refs/tags/{rev}")
+            } else {
+                rev.into()
+            },
+            submodules,
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Flake(flake_ref) => write!(f, "flake {flake_ref}"),
+            Source::File(url) => write!(f, "file {url}"),
+            Source::Tarball(url) => write!(f, "tarball {url}"),
+            Source::Git {
+                url,
+                rev,
+                submodules,
+            } => {
+                write!(f, "git {url} {rev}")?;
+                if *submodules {
+                    f.write_str(" submodules")?;
+                }
+                Ok(())
+            }
+            Source::Fod(expr) => write!(f, "fod {expr}"),
         }
-        flake_prefetch(format!("{prefix}{url}?ref={rev}{submodules}"))
     }
 }
 
-pub fn url_prefetch(url: &str) -> Result<String> {
-    info!("$ nix store prefetch-file --json {url}");
+/// Computes hashes for fetchers
+pub trait Prefetcher: Send + Sync {
+    fn prefetch(&self, source: &Source) -> Result<String>;
+
+    /// Look up whether `rev` is a tag or a branch of the git repository at `url`
+    fn resolve_ref(&self, url: &str, rev: &str) -> Result<RefKind> {
+        ls_refs::resolve_ref(url, rev)
+    }
+
+    /// Look up the commit `HEAD` of the git repository at `url` points to
+    fn head(&self, url: &str) -> Result<String> {
+        ls_refs::head(url)
+    }
+
+    /// Look up the full path of the GitLab project on `host` at the start of `path`
+    fn gitlab_project(&self, host: &str, path: &str) -> Result<Option<String>> {
+        FetchFromGitLab::find_project(host, path)
+    }
 
-    Ok(serde_json::from_slice::<PrefetchOutput>(
-        &Command::new("nix")
-            .arg("store")
-            .arg("prefetch-file")
-            .arg("--extra-experimental-features")
-            .arg("nix-command")
-            .arg("--json")
-            .arg(url)
-            .get_stdout()?,
-    )?
-    .hash)
+    /// Look up the commits of the pull request, merge request, or commit of `page`,
+    /// returning the URLs of patches of those commits
+    fn pin_patch(&self, page: &PatchPage) -> Result<Vec<String>> {
+        page.pin()
+    }
 }
 
-pub fn fod_prefetch(expr: String) -> Result<String> {
-    info!(
-        "$ nix build --extra-experimental-features 'nix-command flakes' --impure --no-link --expr '{expr}'"
-    );
+/// Prefetch everything with nix
+pub struct Nix;
 
-    let Output {
-        stdout,
-        stderr,
-        status,
-    } = Command::new("nix")
-        .arg("build")
+impl Prefetcher for Nix {
+    fn prefetch(&self, source: &Source) -> Result<String> {
+        nix_prefetch(source, false)
+    }
+}
+
+/// Print the nix commands that would be run without running them,
+/// using a fake hash for everything
+pub struct DryRun;
+
+impl Prefetcher for DryRun {
+    fn prefetch(&self, source: &Source) -> Result<String> {
+        nix_prefetch(source, true)
+    }
+}
+
+/// Download and hash files, tarballs, and git repositories without nix,
+/// falling back to nix for everything else
+///
+/// Git repositories are cloned by running `git`, which has to be in `PATH`
+pub struct Native {
+    /// The maximum number of submodules to clone concurrently
+    pub jobs: NonZeroUsize,
+}
+
+impl Prefetcher for Native {
+    fn prefetch(&self, source: &Source) -> Result<String> {
+        match source {
+            Source::File(url) => {
+                info!("fetching {url}");
+
+                let mut hasher = Sha256::new();
+                io::copy(
+                    &mut download(url)?.into_reader(),
+                    &mut HashWriter(&mut hasher),
+                )?;
+
+                Ok(sri(hasher))
+            }
+
+            Source::Tarball(url) => {
+                info!("fetching {url}");
+
+                let dir = tempfile::tempdir()?;
+                unpack(download(url)?.into_reader(), dir.path())?;
+
+                Ok(hash_path(&strip_root(dir.path())?, &|_| false)?)
+            }
+
+            Source::Git {
+                url,
+                rev,
+                submodules,
+            } => git_prefetch(url, rev, *submodules, self.jobs),
+
+            Source::Flake(_) | Source::Fod(_) => Nix.prefetch(source),
+        }
+    }
+}
+
+fn download(url: &str) -> Result<Body> {
+    let mut req = ureq::get(url);
+    if let Some(authorization) = credentials::download_authorization(url) {
+        req = req.header("Authorization", &authorization);
+    }
+    Ok(req.call()?.into_body())
+}
+
+/// Hashes, kinds of refs, GitLab projects, and patches recorded in a JSON file,
+/// keyed by what was looked up
+///
+/// Without a backend to record with, anything that was not recorded is an error
+pub struct Fixtures {
+    path: PathBuf,
+    hashes: Mutex<BTreeMap<String, Value>>,
+    record: Option<Box<dyn Prefetcher>>,
+}
+
+impl Fixtures {
+    pub fn replay(path: PathBuf) -> Result<Self> {
+        let hashes = serde_json::from_slice(&fs::read(&path)?)?;
+        Ok(Self {
+            path,
+            hashes: Mutex::new(hashes),
+            record: None,
+        })
+    }
+
+    /// Prefetch with `backend` and save the hashes to `path`,
+    /// keeping the hashes that were already recorded there
+    pub fn record(path: PathBuf, backend: Box<dyn Prefetcher>) -> Result<Self> {
+        let hashes = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            path,
+            hashes: Mutex::new(hashes),
+            record: Some(backend),
+        })
+    }
+}
+
+impl Fixtures {
+    /// The value recorded for `key`, recording the result of `f` if there is a backend
+    fn get<T: Serialize + DeserializeOwned>(
+        &self,
+        key: String,
+        f: impl FnOnce(&dyn Prefetcher) -> Result<T>,
+    ) -> Result<T> {
+        let Some(backend) = &self.record else {
+            let value = self
+                .hashes
+                .lock()
+                .map_err(|_| eyre!("failed to read recorded hashes"))?
+                .get(&key)
+                .cloned()
+                .ok_or_else(|| eyre!("nothing recorded for {key}"))?;
+            return Ok(serde_json::from_value(value)?);
+        };
+
+        let value = f(backend.as_ref())?;
+
+        let mut hashes = self
+            .hashes
+            .lock()
+            .map_err(|_| eyre!("failed to record hashes"))?;
+        hashes.insert(key, serde_json::to_value(&value)?);
+
+        let mut contents = serde_json::to_vec_pretty(&*hashes)?;
+        contents.push(b'\n');
+        fs::write(&self.path, contents)?;
+
+        Ok(value)
+    }
+}
+
+impl Prefetcher for Fixtures {
+    fn prefetch(&self, source: &Source) -> Result<String> {
+        self.get(source.to_string(), |backend| backend.prefetch(source))
+    }
+
+    fn resolve_ref(&self, url: &str, rev: &str) -> Result<RefKind> {
+        self.get(format!("ref {url} {rev}"), |backend| {
+            Ok(backend.resolve_ref(url, rev)?.to_string())
+        })?
+        .parse()
+    }
+
+    fn head(&self, url: &str) -> Result<String> {
+        self.get(format!("head {url}"), |backend| backend.head(url))
+    }
+
+    // projects that were not found are recorded as empty strings
+    fn gitlab_project(&self, host: &str, path: &str) -> Result<Option<String>> {
+        let project = self.get(format!("gitlab-project {host} {path}"), |backend| {
+            Ok(backend.gitlab_project(host, path)?.unwrap_or_default())
+        })?;
+        Ok(Some(project).filter(|project| !project.is_empty()))
+    }
+
+    fn pin_patch(&self, page: &PatchPage) -> Result<Vec<String>> {
+        self.get(format!("patch {}", page.url()), |backend| {
+            backend.pin_patch(page)
+        })
+    }
+}
+
+const FAKE_HASH: &str = "sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+fn nix_prefetch(source: &Source, dry_run: bool) -> Result<String> {
+    match source {
+        Source::Flake(flake_ref) => flake_prefetch(flake_ref, dry_run),
+        Source::File(url) => {
+            let mut cmd = Command::new("nix");
+            cmd.arg("store")
+                .arg("prefetch-file")
+                .arg("--extra-experimental-features")
+                .arg("nix-command")
+                .arg("--json")
+                .arg(url);
+            info!("{}", CommandLine(&cmd));
+
+            if dry_run {
+                return Ok(FAKE_HASH.into());
+            }
+            Ok(serde_json::from_slice::<PrefetchOutput>(&cmd.get_stdout()?)?.hash)
+        }
+        Source::Tarball(url) => flake_prefetch(&format!("tarball+{url}"), dry_run),
+        Source::Git {
+            url,
+            rev,
+            submodules,
+        } => {
+            // work around for https://github.com/NixOS/nix/issues/5291
+            let prefix = if url.starts_with("git://") {
+                ""
+            } else {
+                "git+"
+            };
+            let submodules = if *submodules { "&submodules=1" } else { "" };
+
+            // abbreviated commit hashes that could not be expanded are passed as refs,
+            // which nix rejects with an error
+            let rev = if is_commit_hash(rev) {
+                format!("allRefs=1&rev={rev}")
+            } else {
+                format!("ref={rev}")
+            };
+
+            flake_prefetch(&format!("{prefix}{url}?{rev}{submodules}"), dry_run)
+        }
+        Source::Fod(expr) => fod_prefetch(expr, dry_run),
+    }
+}
+
+fn flake_prefetch(flake_ref: &str, dry_run: bool) -> Result<String> {
+    let mut cmd = Command::new("nix");
+    cmd.arg("flake")
+        .arg("prefetch")
+        .arg("--extra-experimental-features")
+        .arg("nix-command flakes")
+        .arg("--json")
+        .arg(flake_ref)
+        .envs(credentials::git_env(flake_ref));
+    if let Some(config) = credentials::nix_config(flake_ref) {
+        cmd.env("NIX_CONFIG", config);
+    }
+    info!("{}", CommandLine(&cmd));
+
+    if dry_run {
+        return Ok(FAKE_HASH.into());
+    }
+    Ok(serde_json::from_slice::<PrefetchOutput>(&cmd.get_stdout()?)?.hash)
+}
+
+// check out the revision into a temporary directory and hash it without `.git`, like fetchgit
+fn git_prefetch(url: &str, rev: &str, submodules: bool, jobs: NonZeroUsize) -> Result<String> {
+    let dir = tempfile::tempdir()?;
+    let dir = dir.path();
+
+    git(dir, url, &["init", "-q"])?;
+    git(dir, url, &["remote", "add", "origin", url])?;
+
+    // fetching a single revision does not work for abbreviated commit hashes,
+    // or servers that do not allow fetching unadvertised commits
+    if git(dir, url, &["fetch", "-q", "--depth", "1", "origin", rev]).is_ok() {
+        git(dir, url, &["checkout", "-q", "FETCH_HEAD"])?;
+    } else {
+        git(
+            dir,
+            url,
+            &[
+                "fetch",
+                "-q",
+                "--update-head-ok",
+                "origin",
+                "+refs/*:refs/*",
+            ],
+        )?;
+        git(
+            dir,
+            url,
+            &["checkout", "-q", "--detach", &format!("{rev}^{{commit}}")],
+        )?;
+    }
+
+    if submodules {
+        git(
+            dir,
+            url,
+            &[
+                "submodule",
+                "update",
+                "-q",
+                "--init",
+                "--recursive",
+                "--jobs",
+                &jobs.to_string(),
+            ],
+        )?;
+    }
+
+    Ok(hash_path(dir, &|name| name == b".git")?)
+}
+
+fn git(dir: &Path, url: &str, args: &[&str]) -> Result<()> {
+    info!("$ git {}", args.join(" "));
+    Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        // like fetchgit, ignore the configuration of the user and skip large files of git lfs
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_CONFIG_GLOBAL", "/dev/null")
+        .env("GIT_LFS_SKIP_SMUDGE", "1")
+        .envs(credentials::git_env(url))
+        .get_stdout()?;
+    Ok(())
+}
+
+fn fod_prefetch(expr: &str, dry_run: bool) -> Result<String> {
+    let mut cmd = Command::new("nix");
+    cmd.arg("build")
         .arg("--extra-experimental-features")
         .arg("nix-command flakes")
         .arg("--impure")
         .arg("--no-link")
         .arg("--expr")
-        .arg(expr)
-        .output()?;
+        .arg(expr);
+    info!("{}", CommandLine(&cmd));
+
+    if dry_run {
+        return Ok(FAKE_HASH.into());
+    }
+
+    let Output {
+        stdout,
+        stderr,
+        status,
+    } = cmd.output()?;
 
     if status.success() {
         bail!(
@@ -135,3 +549,25 @@ pub fn fod_prefetch(expr: String) -> Result<String> {
         String::from_utf8_lossy(&stderr),
     ))
 }
+
+// displays a command like it would be typed in a shell
+struct CommandLine<'a>(&'a Command);
+
+impl Display for CommandLine<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "$ {}", self.0.get_program().to_string_lossy())?;
+        for arg in self.0.get_args() {
+            let arg = arg.to_string_lossy();
+            if !arg.is_empty()
+                && arg
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "%+,-./:=@_^".contains(c))
+            {
+                write!(f, " {arg}")?;
+            } else {
+                write!(f, " '{}'", arg.replace('\'', r"'\''"))?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+/// What a revision given on the command line looks like, before looking it up
+#[derive(Debug, PartialEq, Eq)]
+pub enum RevKind {
+    /// A full SHA-1 or SHA-256 object name
+    Commit,
+    /// An abbreviated object name, which could also be the name of a ref
+    ShortCommit,
+    /// The name of a branch or a tag, or a full ref like `refs/tags/v1.0`
+    Ref,
+}
+
+pub fn classify(rev: &str) -> RevKind {
+    if !rev.bytes().all(|c| c.is_ascii_hexdigit()) {
+        return RevKind::Ref;
+    }
+
+    match rev.len() {
+        40 | 64 => RevKind::Commit,
+        // git never abbreviates object names to less than 4 characters
+        4 .. 40 => RevKind::ShortCommit,
+        _ => RevKind::Ref,
+    }
+}
+
+pub fn is_commit_hash(rev: &str) -> bool {
+    classify(rev) == RevKind::Commit
+}
+
+/// A revision and submodule setting carried inside a URL
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct UrlRev {
+    pub rev: Option<String>,
+    pub submodules: Option<bool>,
+}
+
+/// Split the revision out of `url`, returning the URL of the repository and what it pointed to
+///
+/// Understands the web URLs of forges like `https://github.com/owner/repo/tree/v1.2`,
+/// the `ref`, `rev`, and `submodules` query parameters of `git+` URLs, and fragments like
+/// `https://github.com/owner/repo#v1.2`
+pub fn split_url(url: &str) -> (String, UrlRev) {
+    let mut url_rev = UrlRev::default();
+    let (url, fragment) = url.split_once('#').map_or((url, None), |(url, fragment)| {
+        (url, Some(fragment).filter(|fragment| !fragment.is_empty()))
+    });
+    let (base, query) = url
+        .split_once('?')
+        .map_or((url, None), |(base, query)| (base, Some(query)));
+
+    let mut params = Vec::new();
+    let mut query_rev = None;
+    let mut query_ref = None;
+    for param in query.into_iter().flat_map(|query| query.split('&')) {
+        if !base.starts_with("git+") {
+            params.push(param);
+            continue;
+        }
+        match param.split_once('=') {
+            Some(("rev", rev)) if !rev.is_empty() => query_rev = Some(rev),
+            Some(("ref", git_ref)) if !git_ref.is_empty() => query_ref = Some(git_ref),
+            Some(("submodules", value)) => url_rev.submodules = Some(matches!(value, "1" | "true")),
+            _ => params.push(param),
+        }
+    }
+
+    let (mut url, path_rev) = split_path(base);
+    url_rev.rev = query_rev
+        .or(query_ref)
+        .map(Into::into)
+        .or(path_rev)
+        .or(fragment.map(Into::into));
+
+    if !params.is_empty() {
+        url.push('?');
+        url.push_str(&params.join("&"));
+    }
+
+    (url, url_rev)
+}
+
+// web pages of forges that point to a revision of the repository
+fn split_path(url: &str) -> (String, Option<String>) {
+    let unchanged = || (url.into(), None);
+
+    let Some((authority, path)) = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .and_then(|rest| rest.split_once('/'))
+    else {
+        return unchanged();
+    };
+    if path.ends_with(".patch") || path.ends_with(".diff") {
+        return unchanged();
+    }
+    let segments: Vec<_> = path.split('/').collect();
+
+    // pages of files and directories are not pages of the repository, and the revision can not
+    // be told apart from the path when it contains slashes
+    let is_end = |rest: &[&str]| matches!(rest, [] | [""]);
+
+    // GitLab and Gitiles separate the path of the repository from the rest with `-` and `+`
+    let (len, rev) = if let Some(i) = segments.iter().position(|s| *s == "-" || *s == "+") {
+        let rev = match &segments[i ..] {
+            ["-", "commit" | "tags" | "releases", rev, ..] => Some(rev.to_string()),
+            ["-", "tree" | "blob", rev, rest @ ..] if is_end(rest) => Some(rev.to_string()),
+            ["+", "refs", kind, name, rest @ ..] if is_end(rest) => {
+                Some(format!("refs/{kind}/{name}"))
+            }
+            ["+", rev, rest @ ..] if is_end(rest) => Some(rev.to_string()),
+            _ => None,
+        };
+        (i, rev)
+    } else {
+        // GitHub, Gitea, Bitbucket, and Sourcehut put the revision after the owner and the name
+        let rev = match segments.get(2 ..) {
+            Some(["commit" | "commits", rev, ..]) => Some(rev.to_string()),
+            Some(
+                ["tree" | "blob", rev, rest @ ..]
+                | ["src", "branch" | "tag" | "commit", rev, rest @ ..],
+            ) if is_end(rest) => Some(rev.to_string()),
+            Some(["releases", "tag", tag @ ..]) => Some(tag.join("/")),
+            Some(["src", rev, rest @ ..]) if authority == "bitbucket.org" && is_end(rest) => {
+                Some(rev.to_string())
+            }
+            Some(["refs", rev, ..]) if segments[0].starts_with('~') => Some(rev.to_string()),
+            _ => None,
+        };
+        (2, rev)
+    };
+
+    match rev {
+        Some(rev) if !rev.is_empty() => {
+            let scheme = &url[.. url.len() - authority.len() - path.len() - 1];
+            (
+                format!("{scheme}{authority}/{}", segments[.. len].join("/")),
+                Some(rev),
+            )
+        }
+        _ => unchanged(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RevKind, UrlRev, classify, split_url};
+
+    #[test]
+    fn kinds() {
+        for (rev, kind) in [
+            ("16ab26c61b36cdbdc2940c17bdd8b18c32639135", RevKind::Commit),
+            (
+                "8f7a3c0f5d2b6e1a9c4d8e7f6a5b4c3d2e1f0a9b8c7d6e5f4a3b2c1d0e9f8a7b",
+                RevKind::Commit,
+            ),
+            ("16ab26c", RevKind::ShortCommit),
+            ("20230101", RevKind::ShortCommit),
+            ("v0.3.0", RevKind::Ref),
+            ("main", RevKind::Ref),
+            ("abc", RevKind::Ref),
+            ("refs/tags/v0.3.0", RevKind::Ref),
+        ] {
+            assert_eq!(classify(rev), kind, "{rev}");
+        }
+    }
+
+    #[test]
+    fn urls() {
+        let rev = |rev: &str| UrlRev {
+            rev: Some(rev.into()),
+            submodules: None,
+        };
+
+        for (url, expected, url_rev) in [
+            (
+                "https://github.com/nix-community/nurl/tree/v0.3.0",
+                "https://github.com/nix-community/nurl",
+                rev("v0.3.0"),
+            ),
+            (
+                "https://github.com/nix-community/nurl/commit/16ab26c61b36cdbdc2940c17bdd8b18c32639135",
+                "https://github.com/nix-community/nurl",
+                rev("16ab26c61b36cdbdc2940c17bdd8b18c32639135"),
+            ),
+            (
+                "https://github.com/o/r/releases/tag/release/1.0",
+                "https://github.com/o/r",
+                rev("release/1.0"),
+            ),
+            (
+                "https://codeberg.org/o/r/src/tag/v1",
+                "https://codeberg.org/o/r",
+                rev("v1"),
+            ),
+            (
+                "https://bitbucket.org/o/r/src/main",
+                "https://bitbucket.org/o/r",
+                rev("main"),
+            ),
+            (
+                "https://gitlab.com/a/b/c/-/tree/main/",
+                "https://gitlab.com/a/b/c",
+                rev("main"),
+            ),
+            (
+                "https://chromium.googlesource.com/a/b/+/refs/heads/main",
+                "https://chromium.googlesource.com/a/b",
+                rev("refs/heads/main"),
+            ),
+            (
+                "https://bitbucket.org/o/r/src/main/README.md",
+                "https://bitbucket.org/o/r/src/main/README.md",
+                UrlRev::default(),
+            ),
+            (
+                "https://gitlab.com/a/b/c/-/blob/main/README.md",
+                "https://gitlab.com/a/b/c/-/blob/main/README.md",
+                UrlRev::default(),
+            ),
+            (
+                "https://github.com/o/r/tree/feature/x",
+                "https://github.com/o/r/tree/feature/x",
+                UrlRev::default(),
+            ),
+            (
+                "https://chromium.googlesource.com/a/b/+/refs/heads/main/README.md",
+                "https://chromium.googlesource.com/a/b/+/refs/heads/main/README.md",
+                UrlRev::default(),
+            ),
+            (
+                "https://github.com/nix-community/nurl#v0.3.0",
+                "https://github.com/nix-community/nurl",
+                rev("v0.3.0"),
+            ),
+            (
+                "git+https://example.org/r?ref=main&rev=abcdef0&shallow=1&submodules=1",
+                "git+https://example.org/r?shallow=1",
+                UrlRev {
+                    rev: Some("abcdef0".into()),
+                    submodules: Some(true),
+                },
+            ),
+            (
+                "https://git.sr.ht/~o/r/refs/v1.0",
+                "https://git.sr.ht/~o/r",
+                rev("v1.0"),
+            ),
+            (
+                "https://github.com/o/r/commit/16ab26c61b36cdbdc2940c17bdd8b18c32639135.patch",
+                "https://github.com/o/r/commit/16ab26c61b36cdbdc2940c17bdd8b18c32639135.patch",
+                UrlRev::default(),
+            ),
+            (
+                "https://raw.githubusercontent.com/o/r/refs/heads/main/src/lib.rs",
+                "https://raw.githubusercontent.com/o/r/refs/heads/main/src/lib.rs",
+                UrlRev::default(),
+            ),
+            (
+                "https://example.org/file?ref=main",
+                "https://example.org/file?ref=main",
+                UrlRev::default(),
+            ),
+            (
+                "https://github.com/nix-community/nurl",
+                "https://github.com/nix-community/nurl",
+                UrlRev::default(),
+            ),
+        ] {
+            assert_eq!(split_url(url), (expected.into(), url_rev), "{url}");
+        }
+    }
+}
@@ -1,13 +1,33 @@
 use std::{fmt::Write as _, io::Write};
 
-use eyre::{Result, bail};
+use eyre::{Result, bail, eyre};
 use serde_json::json;
 
-use crate::{Url, config::FetcherConfig, fetcher::Fetcher, prefetch::fod_prefetch};
+use crate::{
+    Url, config::FetcherConfig, fetcher::Fetcher, output::FetcherOutput, prefetch::Source,
+};
+
+// the path of a resolved URL, which is always an http or https URL
+fn path(url: &str) -> &str {
+    url.split_once("://")
+        .and_then(|(_, rest)| rest.split_once('/'))
+        .map_or("", |(_, path)| {
+            path.split(['?', '#']).next().unwrap_or(path)
+        })
+}
 
 pub trait RevlessFetcher {
     const NAME: &'static str;
 
+    /// URLs to fetch in place of `url`, looking things up only when `cfg` is given
+    fn resolve_urls(
+        &self,
+        _url: &str,
+        _cfg: Option<&FetcherConfig>,
+    ) -> Result<Option<Vec<String>>> {
+        Ok(None)
+    }
+
     fn fetch(&self, url: &Url, cfg: &FetcherConfig) -> Result<String>;
 
     fn fetch_fod(&self, url: &Url, cfg: &FetcherConfig) -> Result<String> {
@@ -26,71 +46,85 @@ pub trait RevlessFetcher {
 
         expr.push('}');
 
-        fod_prefetch(expr)
+        cfg.prefetch(Source::Fod(expr))
     }
-}
 
-impl<'a, T: RevlessFetcher> Fetcher<'a> for T {
-    fn fetch_nix(&self, out: &mut impl Write, url: &'a Url, mut cfg: FetcherConfig) -> Result<()> {
+    /// Fetch the URLs that `url` resolves to, returning them with their hashes
+    fn fetch_resolved(&self, url: &Url, cfg: &FetcherConfig) -> Result<Vec<(String, String)>> {
         if cfg.has_rev() {
             bail!("{} does not support revisions", Self::NAME);
         }
 
-        let hash = self.fetch(url, &cfg)?;
-        let indent = " ".repeat(cfg.indent);
+        match self.resolve_urls(url.as_str(), Some(cfg))? {
+            Some(urls) => urls
+                .into_iter()
+                .map(|url| {
+                    let hash = self.fetch(&Url::new(&url, path(&url)), cfg)?;
+                    Ok((url, hash))
+                })
+                .collect(),
+            None => Ok(vec![(url.as_str().into(), self.fetch(url, cfg)?)]),
+        }
+    }
 
-        writeln!(out, "{} {{", Self::NAME)?;
+    fn output(&self, url: &str, hash: &str, cfg: &FetcherConfig) -> FetcherOutput {
+        let mut output = FetcherOutput::new(Self::NAME);
+        output.push_str("url", url);
+        output.push_str("hash", hash);
+        cfg.extend_output(&mut output, "");
+        output
+    }
+}
 
-        if let Some(url) = cfg.overwrites.remove("url") {
-            writeln!(out, "{indent}  {url} = {url};")?;
-        } else {
-            writeln!(out, r#"{indent}  url = "{url}";"#)?;
-        }
+// several URLs are written as a list, like the `patches` of a derivation
+fn json_calls(mut calls: Vec<serde_json::Value>) -> serde_json::Value {
+    if calls.len() == 1 {
+        calls.swap_remove(0)
+    } else {
+        calls.into()
+    }
+}
+
+impl<'a, T: RevlessFetcher> Fetcher<'a> for T {
+    fn fetch_nix(&self, out: &mut impl Write, url: &'a Url, cfg: FetcherConfig) -> Result<()> {
+        let fetched = self.fetch_resolved(url, &cfg)?;
+        let indent = " ".repeat(cfg.indent);
 
-        if let Some(hash) = cfg.overwrites.remove("hash") {
-            writeln!(out, "{indent}  {hash} = {hash};")?;
-        } else {
-            writeln!(out, r#"{indent}  hash = "{hash}";"#)?;
+        if let [(url, hash)] = &fetched[..] {
+            return self.output(url, hash, &cfg).write_nix(out, &indent);
         }
 
-        cfg.write_nix_args(out, &indent)?;
-        write!(out, "{indent}}}")?;
+        writeln!(out, "[")?;
+        for (url, hash) in &fetched {
+            write!(out, "{indent}  (")?;
+            self.output(url, hash, &cfg)
+                .write_nix(out, &format!("{indent}  "))?;
+            writeln!(out, ")")?;
+        }
+        write!(out, "{indent}]")?;
 
         Ok(())
     }
 
     fn fetch_hash(&self, out: &mut impl Write, url: &'a Url, cfg: FetcherConfig) -> Result<()> {
-        if cfg.has_rev() {
-            bail!("{} does not support revisions", Self::NAME);
-        }
-
-        let hash = self.fetch(url, &cfg)?;
-        write!(out, "{hash}")?;
+        let hashes: Vec<_> = self
+            .fetch_resolved(url, &cfg)?
+            .into_iter()
+            .map(|(_, hash)| hash)
+            .collect();
+        write!(out, "{}", hashes.join("\n"))?;
 
         Ok(())
     }
 
     fn fetch_json(&self, out: &mut impl Write, url: &'a Url, cfg: FetcherConfig) -> Result<()> {
-        if cfg.has_rev() {
-            bail!("{} does not support revisions", Self::NAME);
-        }
-
-        let hash = self.fetch(url, &cfg)?;
-
-        let mut fetcher_args = json!({
-            "url": url.as_str(),
-            "hash": hash,
-        });
-
-        cfg.extend_fetcher_args(&mut fetcher_args, "");
+        let calls = self
+            .fetch_resolved(url, &cfg)?
+            .into_iter()
+            .map(|(url, hash)| self.output(&url, &hash, &cfg).to_json())
+            .collect::<Result<_>>()?;
 
-        serde_json::to_writer(
-            out,
-            &json!({
-                "fetcher": Self::NAME,
-                "args": fetcher_args,
-            }),
-        )?;
+        serde_json::to_writer(out, &json_calls(calls))?;
 
         Ok(())
     }
@@ -100,16 +134,30 @@ impl<'a, T: RevlessFetcher> Fetcher<'a> for T {
             bail!("{} does not support revisions", Self::NAME);
         }
 
-        serde_json::to_writer(
-            out,
-            &json!({
-                "fetcher": Self::NAME,
-                "args": {
-                    "url": url.as_str(),
-                },
-            }),
-        )?;
+        let calls = self
+            .resolve_urls(url.as_str(), None)?
+            .unwrap_or_else(|| vec![url.as_str().into()])
+            .into_iter()
+            .map(|url| json!({ "fetcher": Self::NAME, "args": { "url": url } }))
+            .collect();
+
+        serde_json::to_writer(out, &json_calls(calls))?;
 
         Ok(())
     }
+
+    fn fetch_output(&self, url: &'a Url, cfg: FetcherConfig) -> Result<FetcherOutput> {
+        let [(resolved, hash)]: [_; 1] =
+            self.fetch_resolved(url, &cfg)?
+                .try_into()
+                .map_err(|fetched: Vec<_>| {
+                    eyre!(
+                        "{url} resolves to {} URLs that need one {} each",
+                        fetched.len(),
+                        Self::NAME,
+                    )
+                })?;
+
+        Ok(self.output(&resolved, &hash, &cfg))
+    }
 }
@@ -0,0 +1,318 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use clap::{ArgMatches, ValueEnum, parser::ValueSource};
+use eyre::{Result, WrapErr, bail, eyre};
+use rustc_hash::FxHashMap;
+use serde::Deserialize;
+
+use crate::{
+    Inference,
+    cli::{FetcherFunction, Opts},
+    credentials,
+};
+
+/// Defaults from `~/.config/nurl/config.toml` and the closest `.nurl.toml`,
+/// where the latter takes precedence
+///
+/// `.nurl.toml` is looked up in the current directory and its parents up to the root of the
+/// repository or the home directory, and can not set `nixpkgs` or `tokens`, since it comes with
+/// the code that is worked on
+///
+/// ```toml
+/// nixpkgs = "<nixpkgs>"
+/// indent = 2
+/// fallback = "fetchgit"
+/// patch-fetcher = "fetchpatch2"
+/// detect = true
+///
+/// [hosts]
+/// "git.example.org" = "fetchFromGitLab"
+/// "github.example.org" = "fetchFromGitHub"
+///
+/// [tokens]
+/// "git.example.org" = "glpat-..."
+/// ```
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Settings {
+    nixpkgs: Option<String>,
+    indent: Option<usize>,
+    fallback: Option<String>,
+    patch_fetcher: Option<String>,
+    detect: Option<bool>,
+    #[serde(default)]
+    hosts: FxHashMap<String, String>,
+    #[serde(default)]
+    tokens: FxHashMap<String, String>,
+}
+
+impl Settings {
+    pub fn load() -> Result<Self> {
+        let mut settings = Self::default();
+
+        let user = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+            _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")),
+        };
+        if let Some(dir) = user {
+            settings.merge(Self::read(&dir.join("nurl").join("config.toml"))?);
+        }
+
+        let home = env::var_os("HOME").map(PathBuf::from);
+        settings.merge(Self::project(&env::current_dir()?, home.as_deref())?);
+
+        Ok(settings)
+    }
+
+    // the closest `.nurl.toml` in `cwd` or its parents, stopping at the root of the repository
+    // or at `home`
+    fn project(cwd: &Path, home: Option<&Path>) -> Result<Option<Self>> {
+        for dir in cwd.ancestors() {
+            let path = dir.join(".nurl.toml");
+            if let Some(project) = Self::read(&path)? {
+                if project.nixpkgs.is_some() || !project.tokens.is_empty() {
+                    bail!(
+                        "{}: nixpkgs and tokens can only be set in the user configuration",
+                        path.display(),
+                    );
+                }
+                return Ok(Some(project));
+            }
+
+            if Some(dir) == home || dir.join(".git").exists() {
+                break;
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn read(path: &Path) -> Result<Option<Self>> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents)
+                .map(Some)
+                .wrap_err_with(|| format!("failed to parse {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn merge(&mut self, other: Option<Self>) {
+        let Some(other) = other else {
+            return;
+        };
+
+        self.nixpkgs = other.nixpkgs.or(self.nixpkgs.take());
+        self.indent = other.indent.or(self.indent);
+        self.fallback = other.fallback.or(self.fallback.take());
+        self.patch_fetcher = other.patch_fetcher.or(self.patch_fetcher.take());
+        self.detect = other.detect.or(self.detect);
+        self.hosts.extend(other.hosts);
+        self.tokens.extend(other.tokens);
+    }
+
+    /// Apply the settings to options that were not specified on the command line
+    pub fn apply(&self, opts: &mut Opts, matches: &ArgMatches) -> Result<()> {
+        let is_default = |id| {
+            matches!(
+                matches.value_source(id),
+                None | Some(ValueSource::DefaultValue)
+            )
+        };
+
+        if let Some(nixpkgs) = &self.nixpkgs
+            && is_default("nixpkgs")
+        {
+            opts.nixpkgs.clone_from(nixpkgs);
+        }
+
+        if let Some(indent) = self.indent
+            && is_default("indent")
+        {
+            opts.indent = indent;
+        }
+
+        if let Some(detect) = self.detect
+            && is_default("detect")
+        {
+            opts.detect = detect;
+        }
+
+        if let Some(fallback) = &self.fallback
+            && is_default("fallback")
+        {
+            opts.fallback = parse_fetcher(fallback)?;
+        }
+
+        Ok(())
+    }
+
+    /// Use the tokens for API requests and prefetching
+    pub fn configure_credentials(&self) {
+        credentials::configure(self.tokens.clone());
+    }
+
+    pub fn inference(&self, opts: &Opts) -> Result<Inference> {
+        let patch = match &self.patch_fetcher {
+            Some(fetcher) => match parse_fetcher(fetcher)? {
+                fetcher @ (FetcherFunction::Fetchpatch | FetcherFunction::Fetchpatch2) => fetcher,
+                _ => bail!("patch-fetcher must be either fetchpatch or fetchpatch2"),
+            },
+            None => FetcherFunction::Fetchpatch,
+        };
+
+        let mut hosts = FxHashMap::default();
+        for (host, fetcher) in &self.hosts {
+            hosts.insert(host.clone(), parse_fetcher(fetcher)?);
+        }
+
+        Ok(Inference {
+            fallback: opts.fallback,
+            patch,
+            hosts,
+            detect: opts.detect,
+        })
+    }
+}
+
+fn parse_fetcher(fetcher: &str) -> Result<FetcherFunction> {
+    FetcherFunction::from_str(fetcher, false).map_err(|e| eyre!(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::{CommandFactory, FromArgMatches};
+    use tempfile::TempDir;
+
+    use super::Settings;
+    use crate::cli::{FetcherFunction, Opts};
+
+    fn merged(user: &str, project: &str) -> Settings {
+        let mut settings = Settings::default();
+        settings.merge(Some(toml::from_str(user).unwrap()));
+        settings.merge(Some(toml::from_str(project).unwrap()));
+        settings
+    }
+
+    fn opts(settings: &Settings, args: &[&str]) -> Opts {
+        let matches = Opts::command()
+            .try_get_matches_from(["nurl"].iter().chain(args))
+            .unwrap();
+        let mut opts = Opts::from_arg_matches(&matches).unwrap();
+        settings.apply(&mut opts, &matches).unwrap();
+        opts
+    }
+
+    #[test]
+    fn merge() {
+        let settings = merged(
+            r#"
+            nixpkgs = "<nixpkgs>"
+            indent = 2
+            fallback = "fetchgit"
+
+            [hosts]
+            "git.example.org" = "fetchFromGitLab"
+            "gitea.example.org" = "fetchFromGitea"
+            "#,
+            r#"
+            indent = 4
+            patch-fetcher = "fetchpatch2"
+
+            [hosts]
+            "git.example.org" = "fetchFromGitHub"
+            "#,
+        );
+
+        let opts = opts(&settings, &["https://git.example.org/o/r"]);
+        assert_eq!(opts.nixpkgs, "<nixpkgs>");
+        assert_eq!(opts.indent, 4);
+        assert!(matches!(opts.fallback, FetcherFunction::Fetchgit));
+
+        let inference = settings.inference(&opts).unwrap();
+        assert!(matches!(inference.patch, FetcherFunction::Fetchpatch2));
+        assert!(matches!(
+            inference.hosts["git.example.org"],
+            FetcherFunction::FetchFromGitHub,
+        ));
+        assert!(matches!(
+            inference.hosts["gitea.example.org"],
+            FetcherFunction::FetchFromGitea,
+        ));
+    }
+
+    #[test]
+    fn command_line() {
+        let settings = merged(
+            r#"
+            nixpkgs = "./nixpkgs"
+            indent = 2
+            detect = false
+            fallback = "fetchgit"
+            "#,
+            "",
+        );
+
+        let opts = opts(
+            &settings,
+            &[
+                "https://git.example.org/o/r",
+                "--nixpkgs",
+                "<nixpkgs>",
+                "--indent",
+                "4",
+                "--fallback",
+                "fetchhg",
+                "--detect",
+            ],
+        );
+        assert_eq!(opts.nixpkgs, "<nixpkgs>");
+        assert_eq!(opts.indent, 4);
+        assert!(opts.detect);
+        assert!(matches!(opts.fallback, FetcherFunction::Fetchhg));
+    }
+
+    #[test]
+    fn invalid() {
+        let settings = merged("[hosts]\n\"git.example.org\" = \"fetchFromNowhere\"", "");
+        let opts = opts(&settings, &["https://git.example.org/o/r"]);
+        assert!(settings.inference(&opts).is_err());
+
+        let settings = merged(r#"patch-fetcher = "fetchurl""#, "");
+        assert!(settings.inference(&opts).is_err());
+
+        assert!(toml::from_str::<Settings>("unknown = 1").is_err());
+    }
+
+    #[test]
+    fn project() {
+        let home = TempDir::new().unwrap();
+        let repo = home.path().join("repo");
+        let cwd = repo.join("pkgs").join("nurl");
+        fs::create_dir_all(&cwd).unwrap();
+        fs::create_dir(repo.join(".git")).unwrap();
+
+        let project = || Settings::project(&cwd, Some(home.path()));
+
+        // files outside of the repository are not read
+        fs::write(home.path().join(".nurl.toml"), "indent = 2").unwrap();
+        assert!(project().unwrap().is_none());
+
+        fs::write(repo.join(".nurl.toml"), "indent = 4").unwrap();
+        assert_eq!(project().unwrap().unwrap().indent, Some(4));
+
+        fs::write(
+            repo.join(".nurl.toml"),
+            "[tokens]\n\"github.com\" = \"ghp_...\"",
+        )
+        .unwrap();
+        assert!(project().is_err());
+        fs::write(repo.join(".nurl.toml"), r#"nixpkgs = "./nixpkgs""#).unwrap();
+        assert!(project().is_err());
+    }
+}
@@ -1,20 +1,99 @@
-use std::{fmt::Write as _, io::Write};
+use std::{fmt::Write as _, num::NonZeroUsize, panic, thread};
 
-use eyre::{Result, bail};
+use eyre::{Result, bail, eyre};
 use itertools::Itertools;
-use serde_json::{Value, json};
 
 use crate::{
     Url,
-    config::FetcherConfig,
-    prefetch::{flake_prefetch, fod_prefetch, git_prefetch, url_prefetch},
+    config::{FetcherConfig, Latest},
+    ls_refs::{self, RefKind},
+    output::{Arg, FetcherOutput},
+    prefetch::{Source, eprintln_tagged},
+    rev::{self, RevKind, is_commit_hash},
+    version::{self, Requirement},
 };
 
+/// A revision to fetch
+pub struct Revision {
+    pub rev: String,
+    /// The branch that was pinned to the commit in `rev`
+    pub branch: Option<String>,
+}
+
+impl<T: Into<String>> From<T> for Revision {
+    fn from(rev: T) -> Self {
+        Self {
+            rev: rev.into(),
+            branch: None,
+        }
+    }
+}
+
+/// A tag, or the tag of a release
+pub struct Tag {
+    pub name: String,
+    pub prerelease: bool,
+}
+
+/// The maximum number of pages to request when listing tags
+const MAX_PAGES: usize = 10;
+
+/// Collect items from pages numbered from 1 with `get`,
+/// until a page has less than `per_page` items or [`MAX_PAGES`] is reached,
+/// which is warned about since the remaining items are ignored
+///
+/// Pages after the first one are requested up to `jobs` at a time
+pub fn paginate<T: Send>(
+    per_page: usize,
+    jobs: NonZeroUsize,
+    get: impl Fn(usize) -> Result<Vec<T>> + Sync,
+) -> Result<Vec<T>> {
+    let mut items = get(1)?;
+    if items.len() < per_page {
+        return Ok(items);
+    }
+
+    // the number of pages is unknown, so some of the pages may be requested needlessly
+    let mut pages = (2 ..= MAX_PAGES).peekable();
+    while pages.peek().is_some() {
+        let chunk: Vec<_> = pages.by_ref().take(jobs.get()).collect();
+        let chunk = thread::scope(|s| {
+            let get = &get;
+            chunk
+                .into_iter()
+                .map(|page| s.spawn(move || get(page)))
+                .collect::<Vec<_>>()
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                .collect::<Vec<_>>()
+        });
+
+        for xs in chunk {
+            let xs = xs?;
+            let done = xs.len() < per_page;
+            items.extend(xs);
+            if done {
+                return Ok(items);
+            }
+        }
+    }
+
+    eprintln_tagged(format_args!(
+        "warning: stopped listing after {MAX_PAGES} pages of {per_page} items, the rest are ignored",
+    ));
+    Ok(items)
+}
+
 pub enum RevKey {
     Const(&'static str),
     RevOrTag,
 }
 
+// fetchers of package registries take a version instead of a revision
+fn is_versioned(rev_key: &RevKey) -> bool {
+    matches!(rev_key, RevKey::Const("version"))
+}
+
 pub trait SimpleFetcher<'a, const N: usize> {
     const HASH_KEY: &'static str = "hash";
     const HOST_KEY: &'static str = "domain";
@@ -23,12 +102,14 @@ pub trait SimpleFetcher<'a, const N: usize> {
     const REV_KEY: RevKey = RevKey::Const("rev");
     const SUBMODULES_DEFAULT: bool = false;
     const SUBMODULES_KEY: Option<&'static str> = None;
+    /// The argument to set to `true` for private repositories
+    const PRIVATE_KEY: Option<&'static str> = None;
 
     fn rev_entry<'b>(&self, rev: &'b str) -> (&'static str, &'b str) {
         match Self::REV_KEY {
             RevKey::Const(rev_key) => (rev_key, rev),
             RevKey::RevOrTag => (
-                if rev.len() == 40 { "rev" } else { "tag" },
+                if is_commit_hash(rev) { "rev" } else { "tag" },
                 rev.strip_prefix("refs/tags/").unwrap_or(rev),
             ),
         }
@@ -55,164 +136,310 @@ pub trait SimpleFetcher<'a, const N: usize> {
         Some(xs)
     }
 
+    /// The canonical values looked up with an API, replacing the values from the URL
+    fn resolve_values(&self, _: &[&str; N], _: &FetcherConfig) -> Result<Option<[String; N]>> {
+        Ok(None)
+    }
+
     fn resolve_submodules(&self, submodules: Option<bool>) -> bool {
         submodules.is_some_and(|submodules| submodules ^ Self::SUBMODULES_DEFAULT)
     }
 
-    fn fetch_rev(&self, _: &[&str; N]) -> Result<String> {
-        bail!(
-            "{} does not support fetching the latest revision",
-            Self::NAME,
-        );
+    /// Check that private repositories can be fetched, which requires credentials
+    fn check_private(&self) -> Result<()> {
+        bail!("{} does not support private repositories", Self::NAME);
     }
 
-    fn fetch_fod(
+    /// The URL of the git repository, used to list its refs with the smart HTTP protocol
+    fn git_url(&self, _: &[&str; N]) -> Option<String> {
+        None
+    }
+
+    fn fetch_rev(&self, values: &[&str; N]) -> Result<String> {
+        let Some(url) = self.git_url(values) else {
+            bail!(
+                "{} does not support fetching the latest revision",
+                Self::NAME,
+            );
+        };
+        ls_refs::head(&url)
+    }
+
+    /// Tags of the repository, or tags of releases excluding drafts if `releases` is true
+    fn fetch_tags(
         &self,
         values: &[&str; N],
-        rev_key: &'static str,
-        rev: &str,
-        submodules: bool,
-        cfg: &FetcherConfig,
-    ) -> Result<String> {
-        let mut expr = format!(r#"(import({}){{}}).{}{{"#, cfg.nixpkgs, Self::NAME);
+        releases: bool,
+        _: &FetcherConfig,
+    ) -> Result<Vec<Tag>> {
+        let url = self.git_url(values).filter(|_| !releases);
+        let Some(url) = url else {
+            bail!(
+                "{} does not support fetching the latest {}",
+                Self::NAME,
+                if releases { "release" } else { "tag" },
+            );
+        };
+
+        Ok(ls_refs::ls_refs(&url, &["refs/tags/"])?
+            .into_iter()
+            .filter_map(|r| {
+                Some(Tag {
+                    name: r.name.strip_prefix("refs/tags/")?.into(),
+                    prerelease: false,
+                })
+            })
+            .collect())
+    }
 
-        if let Some(host) = self.host() {
-            write!(expr, r#"{}="{host}";"#, Self::HOST_KEY)?;
+    /// Versions of the package excluding yanked ones, for fetchers of package registries
+    fn fetch_versions(&self, _: &[&str; N]) -> Result<Vec<String>> {
+        bail!("{} does not support version requirements", Self::NAME);
+    }
+
+    /// The revision from the command line, or the latest revision if there is none
+    ///
+    /// Version requirements like `^1.4` are resolved against versions of packages
+    /// for fetchers of package registries, and against tags for git forges,
+    /// while branches are pinned to the commits they point to
+    fn resolve_rev(&self, values: &[&str; N], cfg: &FetcherConfig) -> Result<Revision> {
+        if cfg.private {
+            self.check_private()?;
         }
 
-        if let Some(group) = self.group() {
-            write!(expr, r#"group="{group}";"#)?;
+        let Some(rev) = &cfg.rev else {
+            return Ok(self.latest_rev(values, cfg)?.into());
+        };
+
+        if let Some(req) = Requirement::parse(rev) {
+            return match Self::REV_KEY {
+                RevKey::Const("version") => {
+                    let versions = self.fetch_versions(values)?;
+                    req.latest(versions.iter().map(String::as_str), cfg.prerelease)
+                        .map(Into::into)
+                        .ok_or_else(|| eyre!("no version of {} matches {rev}", values[0]))
+                }
+                RevKey::RevOrTag => Ok(self.select_tag(values, false, Some(&req), cfg)?.into()),
+                RevKey::Const(_) => Ok(rev.as_str().into()),
+            };
         }
 
-        for (key, value) in Self::KEYS.iter().zip(values) {
-            write!(expr, r#"{key}="{value}";"#)?;
+        let kind = rev::classify(rev);
+        if !matches!(Self::REV_KEY, RevKey::RevOrTag)
+            || kind == RevKind::Commit
+            || rev.starts_with("refs/tags/")
+        {
+            return Ok(rev.as_str().into());
+        }
+
+        // refs take precedence over abbreviated commit hashes, like they do in git
+        if let Some(url) = self.git_url(values) {
+            match cfg.resolve_ref(&url, rev)? {
+                RefKind::Tag => return Ok(rev.as_str().into()),
+                RefKind::Branch(commit) => {
+                    return Ok(Revision {
+                        rev: commit,
+                        branch: Some(rev.strip_prefix("refs/heads/").unwrap_or(rev).into()),
+                    });
+                }
+                RefKind::Unknown if kind == RevKind::Ref => {
+                    return Err(ls_refs::not_found(&url, rev));
+                }
+                RefKind::Unknown => {}
+            }
         }
 
+        if kind == RevKind::ShortCommit
+            && let Some(commit) = self.expand_commit(values, rev)?
+        {
+            return Ok(commit.into());
+        }
+
+        Ok(rev.as_str().into())
+    }
+
+    /// The full commit hash of the abbreviated commit hash `short`
+    ///
+    /// By default, only commits at the tips of branches and tags can be found
+    fn expand_commit(&self, values: &[&str; N], short: &str) -> Result<Option<String>> {
+        match self.git_url(values) {
+            Some(url) => ls_refs::expand_commit(&url, short),
+            None => Ok(None),
+        }
+    }
+
+    fn latest_rev(&self, values: &[&str; N], cfg: &FetcherConfig) -> Result<String> {
+        if is_versioned(&Self::REV_KEY) {
+            let versions = self.fetch_versions(values)?;
+            return version::latest(versions.iter().map(String::as_str), cfg.prerelease)
+                .map(Into::into)
+                .ok_or_else(|| eyre!("no versions of {} found", values[0]));
+        }
+
+        let releases = match cfg.latest {
+            // --tag-pattern without --latest-release implies --latest-tag
+            Latest::Commit if cfg.tag_pattern.is_none() => return self.fetch_rev(values),
+            Latest::Commit | Latest::Tag => false,
+            Latest::Release => true,
+        };
+
+        self.select_tag(values, releases, None, cfg)
+    }
+
+    /// The tag with the highest version that satisfies `req` and matches `--tag-pattern`
+    fn select_tag(
+        &self,
+        values: &[&str; N],
+        releases: bool,
+        req: Option<&Requirement>,
+        cfg: &FetcherConfig,
+    ) -> Result<String> {
+        let tags = self.fetch_tags(values, releases, cfg)?;
+        let tags = tags
+            .iter()
+            .filter(|tag| cfg.prerelease || !tag.prerelease)
+            .filter_map(|tag| Some((cfg.tag_version(&tag.name)?, tag.name.as_str())));
+
+        version::max_by_version(tags, req, cfg.prerelease)
+            .map(Into::into)
+            .ok_or_else(|| {
+                let kind = if releases { "releases" } else { "tags" };
+                match req.and(cfg.rev.as_ref()) {
+                    Some(rev) => eyre!("no {kind} with a version matching {rev} found"),
+                    None => eyre!("no {kind} with a version found"),
+                }
+            })
+    }
+
+    fn fetch_fod(
+        &self,
+        values: &[&str; N],
+        rev_key: &'static str,
+        rev: &str,
+        submodules: bool,
+        cfg: &FetcherConfig,
+    ) -> Result<String> {
+        let mut expr = format!(r#"(import({}){{}}).{}{{"#, cfg.nixpkgs, Self::NAME);
+        self.write_fod_args(&mut expr, values, rev_key, rev, submodules, cfg)?;
         write!(
             expr,
-            r#"{rev_key}="{rev}";{}="sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";"#,
+            r#"{}="sha256-AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";}}"#,
             Self::HASH_KEY,
         )?;
 
-        if submodules && let Some(key) = Self::SUBMODULES_KEY {
-            write!(expr, "{key}={};", !Self::SUBMODULES_DEFAULT)?;
-        }
+        cfg.prefetch(Source::Fod(expr))
+    }
 
-        for (key, value) in &cfg.args {
-            write!(expr, "{key}={value};")?;
-        }
-        for (key, value) in &cfg.args_str {
-            write!(expr, r#"{key}="{value}";"#)?;
+    /// Everything that affects the hash, if the revision is immutable
+    fn cache_key(
+        &self,
+        values: &[&str; N],
+        rev_key: &'static str,
+        rev: &str,
+        submodules: bool,
+        cfg: &FetcherConfig,
+    ) -> Result<Option<String>> {
+        if !(rev_key == "version" || is_commit_hash(rev)) {
+            return Ok(None);
         }
 
-        expr.push('}');
+        // how extra arguments affect the hash depends on the version of nixpkgs
+        let mut key = if cfg.has_args() {
+            format!("(import({}){{}}).{}{{", cfg.nixpkgs, Self::NAME)
+        } else {
+            format!("{}{{", Self::NAME)
+        };
+        self.write_fod_args(&mut key, values, rev_key, rev, submodules, cfg)?;
+        key.push('}');
 
-        fod_prefetch(expr)
+        Ok(Some(key))
     }
 
-    #[allow(clippy::too_many_arguments)]
-    fn write_nix(
+    fn write_fod_args(
         &self,
-        out: &mut impl Write,
+        expr: &mut String,
         values: &[&str; N],
         rev_key: &'static str,
         rev: &str,
-        hash: String,
         submodules: bool,
-        mut cfg: FetcherConfig,
+        cfg: &FetcherConfig,
     ) -> Result<()> {
-        let indent = " ".repeat(cfg.indent);
-
-        writeln!(out, "{} {{", Self::NAME)?;
-
-        if let Some(host) = cfg.overwrites.remove(Self::HOST_KEY) {
-            writeln!(out, r#"{indent}  {} = {host};"#, Self::HOST_KEY)?;
-        } else if let Some(host) = self.host() {
-            writeln!(out, r#"{indent}  {} = "{host}";"#, Self::HOST_KEY)?;
+        if let Some(host) = self.host() {
+            write!(expr, r#"{}="{host}";"#, Self::HOST_KEY)?;
         }
 
-        if let Some(group) = cfg.overwrites.remove("group") {
-            writeln!(out, r#"{indent}  group = {group};"#)?;
-        } else if let Some(group) = self.group() {
-            writeln!(out, r#"{indent}  group = "{group}";"#)?;
+        if let Some(group) = self.group() {
+            write!(expr, r#"group="{group}";"#)?;
         }
 
         for (key, value) in Self::KEYS.iter().zip(values) {
-            if let Some(value) = cfg.overwrites.remove(*key) {
-                writeln!(out, r#"{indent}  {key} = {value};"#)?;
-            } else {
-                writeln!(out, r#"{indent}  {key} = "{value}";"#)?;
-            }
+            write!(expr, r#"{key}="{value}";"#)?;
         }
 
-        if let Some(rev) = &cfg.overwrite_rev {
-            writeln!(out, "{indent}  {rev_key} = {rev};")?;
-        } else if let Some(rev) = cfg.overwrites.remove(rev_key) {
-            writeln!(out, "{indent}  {rev_key} = {rev};")?;
-        } else {
-            writeln!(out, r#"{indent}  {rev_key} = "{rev}";"#)?;
-        }
-        if let Some(hash) = cfg.overwrites.remove(Self::HASH_KEY) {
-            writeln!(out, "{indent}  {} = {hash};", Self::HASH_KEY)?;
-        } else {
-            writeln!(out, r#"{indent}  {} = "{hash}";"#, Self::HASH_KEY)?;
+        write!(expr, r#"{rev_key}="{rev}";"#)?;
+
+        if submodules && let Some(key) = Self::SUBMODULES_KEY {
+            write!(expr, "{key}={};", !Self::SUBMODULES_DEFAULT)?;
         }
 
-        if let Some(key) = Self::SUBMODULES_KEY {
-            if let Some(submodules) = cfg.overwrites.remove(key) {
-                writeln!(out, "{indent}  {key} = {submodules};")?;
-            } else if submodules {
-                writeln!(out, "{indent}  {key} = {};", !Self::SUBMODULES_DEFAULT)?;
-            }
+        if cfg.private
+            && let Some(key) = Self::PRIVATE_KEY
+        {
+            write!(expr, "{key}=true;")?;
         }
 
-        cfg.write_nix_args(out, &indent)?;
-        write!(out, "{indent}}}")?;
+        for (key, value) in cfg.args.iter().sorted() {
+            write!(expr, "{key}={value};")?;
+        }
+        for (key, value) in cfg.args_str.iter().sorted() {
+            write!(expr, r#"{key}="{value}";"#)?;
+        }
 
         Ok(())
     }
 
     #[allow(clippy::too_many_arguments)]
-    fn write_json(
+    fn get_output(
         &self,
-        out: &mut impl Write,
         values: &[&str; N],
         rev_key: &'static str,
         rev: &str,
         hash: String,
         submodules: bool,
-        cfg: FetcherConfig,
-    ) -> Result<()> {
-        let mut fetcher_args = Value::from_iter(
-            Self::KEYS
-                .into_iter()
-                .zip(*values)
-                .chain([(rev_key, rev), (Self::HASH_KEY, hash.as_ref())]),
-        );
+        branch: Option<&str>,
+        cfg: &FetcherConfig,
+    ) -> FetcherOutput {
+        let mut output = FetcherOutput::new(Self::NAME);
 
         if let Some(host) = self.host() {
-            fetcher_args[Self::HOST_KEY] = json!(host);
+            output.push_str(Self::HOST_KEY, host);
         }
 
         if let Some(group) = self.group() {
-            fetcher_args["group"] = json!(group);
+            output.push_str("group", group);
         }
 
+        for (key, value) in Self::KEYS.iter().zip(values) {
+            output.push_str(*key, *value);
+        }
+
+        output.push_str(rev_key, rev);
+        output.push_str(Self::HASH_KEY, hash);
+
         if submodules && let Some(key) = Self::SUBMODULES_KEY {
-            fetcher_args[key] = json!(!Self::SUBMODULES_DEFAULT);
+            output.push(key, Arg::Bool(!Self::SUBMODULES_DEFAULT));
         }
 
-        cfg.extend_fetcher_args(&mut fetcher_args, rev_key);
+        if cfg.private
+            && let Some(key) = Self::PRIVATE_KEY
+        {
+            output.push(key, Arg::Bool(true));
+        }
 
-        serde_json::to_writer(
-            out,
-            &json!({
-                "fetcher": Self::NAME,
-                "args": fetcher_args,
-            }),
-        )?;
+        cfg.extend_output(&mut output, rev_key);
+        output.branch = branch.map(Into::into);
 
-        Ok(())
+        output
     }
 }
 
@@ -245,14 +472,14 @@ pub trait SimpleGitFetcher<'a, const N: usize>: SimpleFetcher<'a, N> {
         if cfg.has_args() {
             self.fetch_fod(values, rev_key, rev, submodules, cfg)
         } else if submodules {
-            git_prefetch(
-                true,
+            cfg.prefetch(Source::git(
                 &self.get_repo_url(values),
+                rev_key,
                 rev,
                 !Self::SUBMODULES_DEFAULT,
-            )
+            ))
         } else {
-            flake_prefetch(self.get_flake_ref(values, rev))
+            cfg.prefetch(Source::Flake(self.get_flake_ref(values, rev)))
         }
     }
 }
@@ -273,9 +500,36 @@ pub trait SimpleUrlFetcher<'a, const N: usize>: SimpleFetcher<'a, N> {
         if cfg.has_args() {
             self.fetch_fod(values, rev_key, rev, submodules, cfg)
         } else if Self::UNPACK {
-            flake_prefetch(format!("tarball+{}", self.get_url(values, rev)))
+            cfg.prefetch(Source::Tarball(self.get_url(values, rev)))
         } else {
-            url_prefetch(&self.get_url(values, rev))
+            cfg.prefetch(Source::File(self.get_url(values, rev)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroUsize;
+
+    use super::paginate;
+
+    #[test]
+    fn pages() {
+        for jobs in [1, 3, 16] {
+            let jobs = NonZeroUsize::new(jobs).unwrap();
+            let items = paginate(2, jobs, |page| Ok(vec![page; if page < 4 { 2 } else { 1 }]));
+            assert_eq!(items.unwrap(), [1, 1, 2, 2, 3, 3, 4]);
+
+            let items = paginate(2, jobs, |page| Ok(vec![page; 2]));
+            assert_eq!(items.unwrap().len(), 20);
+
+            let items = paginate(2, jobs, |page| {
+                if page == 3 {
+                    eyre::bail!("failed");
+                }
+                Ok(vec![page; 2])
+            });
+            assert!(items.is_err());
         }
     }
 }
@@ -0,0 +1,927 @@
+use std::{fmt::Write as _, fs, ops::Range, path::Path};
+
+use clap::ValueEnum;
+use eyre::{Result, bail, eyre};
+use rustc_hash::FxHashMap;
+
+use crate::{
+    Inference,
+    cli::FetcherFunction,
+    config::{FetcherConfig, Latest},
+    output::Arg,
+    prefetch,
+};
+
+const REV_KEYS: [&str; 4] = ["rev", "tag", "ref", "version"];
+const HASH_KEYS: [&str; 2] = ["hash", "sha256"];
+const SUBMODULES_KEYS: [&str; 3] = ["fetchSubmodules", "fetchSubrepos", "submodules"];
+// arguments that nurl derives from the URL
+const URL_KEYS: [&str; 8] = [
+    "domain",
+    "githubBase",
+    "group",
+    "owner",
+    "pkg",
+    "pname",
+    "repo",
+    "url",
+];
+
+/// Which fetcher calls to update
+pub enum Selection {
+    All,
+    /// The call that spans the line, 1-indexed
+    Line(usize),
+    /// Calls bound to the attribute
+    Attr(String),
+}
+
+struct Call {
+    fetcher: FetcherFunction,
+    name: String,
+    attr: Option<String>,
+    lines: Range<usize>,
+    bindings: Vec<Binding>,
+}
+
+struct Binding {
+    key: String,
+    key_span: Range<usize>,
+    value_span: Range<usize>,
+    value: Value,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Code,
+    Comment,
+    String,
+}
+
+enum Value {
+    String(String),
+    Bool(bool),
+    Expr(String),
+    /// `inherit key;`, with the span of the key as the span of the value
+    Inherit,
+}
+
+/// A name bound to a string literal somewhere in the file, like `version = "1.2.3";`
+struct Var {
+    span: Range<usize>,
+    value: String,
+}
+
+/// Variables by name, `None` for names that are bound more than once
+type Vars = FxHashMap<String, Option<Var>>;
+
+/// A value made of a variable, like `version`, `"v${version}"`, or `inherit version;`
+struct Template<'a> {
+    prefix: &'a str,
+    var: &'a Var,
+    suffix: &'a str,
+}
+
+impl Binding {
+    fn template<'a>(&'a self, vars: &'a Vars) -> Option<Template<'a>> {
+        let (prefix, name, suffix) = match &self.value {
+            Value::Expr(text) => parse_template(text)?,
+            Value::Inherit => ("", self.key.as_str(), ""),
+            Value::String(_) | Value::Bool(_) => return None,
+        };
+        Some(Template {
+            prefix,
+            var: vars.get(name)?.as_ref()?,
+            suffix,
+        })
+    }
+
+    /// The string the value evaluates to, if it is a string literal or made of a variable
+    fn string(&self, vars: &Vars) -> Option<String> {
+        match &self.value {
+            Value::String(value) => Some(value.clone()),
+            _ => {
+                let Template {
+                    prefix,
+                    var,
+                    suffix,
+                } = self.template(vars)?;
+                Some(format!("{prefix}{}{suffix}", var.value))
+            }
+        }
+    }
+
+    fn not_literal(&self) -> eyre::Report {
+        match self.value {
+            Value::Inherit => eyre!("{} is inherited", self.key),
+            _ => eyre!("{} is not a string literal", self.key),
+        }
+    }
+}
+
+/// Update the revisions and hashes of fetcher calls in a Nix file in place
+///
+/// Calls are updated to `rev` if specified, otherwise the latest revision.
+/// Returns the number of calls that failed to update
+pub fn run(
+    path: &Path,
+    selection: &Selection,
+    rev: Option<&str>,
+    inference: &Inference,
+    cfg: &FetcherConfig,
+) -> Result<usize> {
+    let mut src = fs::read_to_string(path)?;
+    let vars = find_vars(&src);
+    let calls: Vec<_> = find_calls(&src)
+        .into_iter()
+        .filter(|call| match selection {
+            Selection::All => true,
+            Selection::Line(line) => call.lines.contains(line),
+            Selection::Attr(attr) => call
+                .attr
+                .as_ref()
+                .is_some_and(|x| x == attr || x.rsplit_once('.').is_some_and(|(_, x)| x == attr)),
+        })
+        .collect();
+
+    if calls.is_empty() {
+        bail!("no matching fetcher calls found in {}", path.display());
+    }
+
+    let mut edits = Vec::new();
+    let mut failures = 0;
+
+    for call in &calls {
+        match update_call(call, &vars, rev, inference, cfg) {
+            Ok(call_edits) => edits.extend(call_edits),
+            Err(e) => {
+                failures += 1;
+                eprintln!(
+                    "{}:{}: failed to update {}: {e:#}",
+                    path.display(),
+                    call.lines.start,
+                    call.name,
+                );
+            }
+        }
+    }
+
+    // calls can share variables like `version`
+    edits.sort_unstable_by(|(x, text_x), (y, text_y)| {
+        (x.start, x.end, text_x).cmp(&(y.start, y.end, text_y))
+    });
+    edits.dedup();
+    if let Some(edits) = edits
+        .windows(2)
+        .find(|edits| edits[0].0.end > edits[1].0.start)
+    {
+        bail!("conflicting updates of {}", &src[edits[1].0.clone()]);
+    }
+    for (span, text) in edits.into_iter().rev() {
+        src.replace_range(span, &text);
+    }
+    fs::write(path, src)?;
+
+    Ok(failures)
+}
+
+fn update_call(
+    call: &Call,
+    vars: &Vars,
+    rev: Option<&str>,
+    inference: &Inference,
+    base: &FetcherConfig,
+) -> Result<Vec<(Range<usize>, String)>> {
+    let mut cfg = base.clone();
+    cfg.rev = rev.map(Into::into);
+
+    let old_rev = call
+        .bindings
+        .iter()
+        .find(|binding| REV_KEYS.contains(&binding.key.as_str()));
+    match old_rev {
+        Some(old_rev) => {
+            old_rev.string(vars).ok_or_else(|| old_rev.not_literal())?;
+            // keep calls pinned to tags on tags instead of moving them to the default branch
+            if rev.is_none() && old_rev.key == "tag" && matches!(cfg.latest, Latest::Commit) {
+                cfg.latest = Latest::Tag;
+            }
+        }
+        None if !is_revless(call.fetcher) => bail!("no revision found"),
+        None => {}
+    }
+
+    let old_hash = call
+        .bindings
+        .iter()
+        .find(|binding| HASH_KEYS.contains(&binding.key.as_str()))
+        .ok_or_else(|| eyre!("no hash found"))?;
+    if matches!(old_hash.value, Value::Inherit) {
+        bail!("{} is inherited", old_hash.key);
+    }
+
+    for binding in &call.bindings {
+        let key = binding.key.as_str();
+        if REV_KEYS.contains(&key) || HASH_KEYS.contains(&key) || URL_KEYS.contains(&key) {
+            continue;
+        }
+
+        if let Some(value) = binding.template(vars).and(binding.string(vars)) {
+            cfg.args_str.insert(binding.key.clone(), value);
+            continue;
+        }
+
+        match &binding.value {
+            Value::Bool(value) if SUBMODULES_KEYS.contains(&key) => {
+                cfg.submodules = Some(*value);
+            }
+            Value::Bool(value) if key == "private" => {
+                cfg.private = *value;
+            }
+            Value::String(value) => {
+                cfg.args_str.insert(binding.key.clone(), value.clone());
+            }
+            Value::Bool(value) => {
+                cfg.args.insert(binding.key.clone(), value.to_string());
+            }
+            Value::Expr(value) => {
+                cfg.args.insert(binding.key.clone(), value.clone());
+            }
+            // the inherited value is not known, so the fetcher uses its default
+            Value::Inherit => {}
+        }
+    }
+
+    let url = source_url(call, vars)?;
+    let output = prefetch(&url, Some(call.fetcher), inference, cfg)?;
+
+    let mut edits = Vec::new();
+
+    let new_rev = output
+        .args
+        .iter()
+        .find(|(key, _)| REV_KEYS.contains(&key.as_str()));
+    match (old_rev, new_rev) {
+        (Some(old_rev), Some((key, Arg::String(value)))) => match &old_rev.value {
+            Value::String(_) => {
+                if *key != old_rev.key {
+                    edits.push((old_rev.key_span.clone(), key.clone()));
+                }
+                edits.push((old_rev.value_span.clone(), format!(r#""{value}""#)));
+            }
+            _ => {
+                if *key != old_rev.key {
+                    bail!("{} would need to be replaced with {key}", old_rev.key);
+                }
+                let Some(Template {
+                    prefix,
+                    var,
+                    suffix,
+                }) = old_rev.template(vars)
+                else {
+                    return Err(old_rev.not_literal());
+                };
+                let Some(value) = value
+                    .strip_prefix(prefix)
+                    .and_then(|value| value.strip_suffix(suffix))
+                else {
+                    bail!("{key} {value} does not match {}", &old_rev.key);
+                };
+                edits.push((var.span.clone(), format!(r#""{value}""#)));
+            }
+        },
+        (Some(_), _) => bail!("{} does not output a revision", call.name),
+        (None, Some((key, _))) => bail!("{key} is not specified"),
+        (None, None) => {}
+    }
+
+    let Some((key, Arg::String(hash))) = output
+        .args
+        .iter()
+        .find(|(key, _)| HASH_KEYS.contains(&key.as_str()))
+    else {
+        bail!("{} does not output a hash", call.name);
+    };
+    if *key != old_hash.key {
+        edits.push((old_hash.key_span.clone(), key.clone()));
+    }
+    edits.push((old_hash.value_span.clone(), format!(r#""{hash}""#)));
+
+    Ok(edits)
+}
+
+// fetchers that take no revision
+fn is_revless(fetcher: FetcherFunction) -> bool {
+    matches!(
+        fetcher,
+        FetcherFunction::Fetchpatch
+            | FetcherFunction::Fetchpatch2
+            | FetcherFunction::Fetchurl
+            | FetcherFunction::Fetchzip,
+    )
+}
+
+// reconstruct the URL nurl would have generated the call from
+fn source_url(call: &Call, vars: &Vars) -> Result<String> {
+    let get = |key| {
+        call.bindings
+            .iter()
+            .find(|binding| binding.key == key)
+            .map(|binding| binding.string(vars).ok_or_else(|| binding.not_literal()))
+            .transpose()
+    };
+    let req = |key| get(key)?.ok_or_else(|| eyre!("missing argument {key}"));
+
+    Ok(match call.fetcher {
+        FetcherFunction::FetchCrate => format!("https://crates.io/crates/{}", req("pname")?),
+        FetcherFunction::FetchFromBitbucket => {
+            format!("https://bitbucket.org/{}/{}", req("owner")?, req("repo")?)
+        }
+        FetcherFunction::FetchFromGitHub => format!(
+            "https://{}/{}/{}",
+            get("githubBase")?.as_deref().unwrap_or("github.com"),
+            req("owner")?,
+            req("repo")?,
+        ),
+        FetcherFunction::FetchFromGitLab => {
+            let mut url = format!(
+                "https://{}/",
+                get("domain")?.as_deref().unwrap_or("gitlab.com")
+            );
+            if let Some(group) = get("group")? {
+                write!(url, "{group}/")?;
+            }
+            write!(url, "{}/{}", req("owner")?, req("repo")?)?;
+            url
+        }
+        FetcherFunction::FetchFromGitea => {
+            format!(
+                "https://{}/{}/{}",
+                req("domain")?,
+                req("owner")?,
+                req("repo")?,
+            )
+        }
+        FetcherFunction::FetchFromRepoOrCz => format!("https://repo.or.cz/{}", req("repo")?),
+        FetcherFunction::FetchFromSourcehut => format!(
+            "https://{}/{}/{}",
+            get("domain")?.as_deref().unwrap_or("git.sr.ht"),
+            req("owner")?,
+            req("repo")?,
+        ),
+        FetcherFunction::FetchHex => format!("https://hex.pm/packages/{}", req("pkg")?),
+        FetcherFunction::FetchPypi => format!("https://pypi.org/project/{}", req("pname")?),
+        FetcherFunction::BuiltinsFetchGit
+        | FetcherFunction::FetchFromGitiles
+        | FetcherFunction::Fetchgit
+        | FetcherFunction::Fetchhg
+        | FetcherFunction::Fetchpatch
+        | FetcherFunction::Fetchpatch2
+        | FetcherFunction::Fetchsvn
+        | FetcherFunction::Fetchurl
+        | FetcherFunction::Fetchzip => req("url")?,
+    })
+}
+
+fn find_calls(src: &str) -> Vec<Call> {
+    let kinds = classify(src);
+    let bytes = src.as_bytes();
+    let names: Vec<_> = FetcherFunction::value_variants()
+        .iter()
+        .filter_map(|fetcher| Some((*fetcher, fetcher.to_possible_value()?.get_name().to_owned())))
+        .collect();
+
+    let mut calls = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if kinds[i] != Kind::Code || (i != 0 && is_ident(bytes[i - 1])) {
+            i += 1;
+            continue;
+        }
+
+        let Some((fetcher, name)) = names.iter().find(|(_, name)| {
+            src[i ..].starts_with(name.as_str())
+                && !bytes.get(i + name.len()).copied().is_some_and(is_ident)
+        }) else {
+            i += 1;
+            continue;
+        };
+
+        let mut j = skip_trivia(src, &kinds, i + name.len());
+        if src[j ..].starts_with("rec") && !bytes.get(j + 3).copied().is_some_and(is_ident) {
+            j = skip_trivia(src, &kinds, j + 3);
+        }
+        if bytes.get(j) != Some(&b'{') || kinds[j] != Kind::Code {
+            i += name.len();
+            continue;
+        }
+
+        let Some((bindings, end)) = parse_bindings(src, &kinds, j + 1) else {
+            i += name.len();
+            continue;
+        };
+
+        calls.push(Call {
+            fetcher: *fetcher,
+            name: name.clone(),
+            attr: find_attr(src, &kinds, i),
+            lines: line_of(src, i) .. line_of(src, end) + 1,
+            bindings,
+        });
+        i = end + 1;
+    }
+
+    calls
+}
+
+// the attribute a call is bound to, e.g. `src` in `src = fetchurl { ... }`
+fn find_attr(src: &str, kinds: &[Kind], mut i: usize) -> Option<String> {
+    let bytes = src.as_bytes();
+    // skip qualifiers like `pkgs.`
+    while i != 0 && (is_ident(bytes[i - 1]) || bytes[i - 1] == b'.') {
+        i -= 1;
+    }
+    let i = src[.. i].trim_end().len();
+    if i == 0 || bytes[i - 1] != b'=' || kinds[i - 1] != Kind::Code {
+        return None;
+    }
+
+    let end = src[.. i - 1].trim_end().len();
+    let start = src[.. end]
+        .rfind(|c: char| !(c.is_ascii() && (is_ident(c as u8) || c == '.')))
+        .map_or(0, |i| i + 1);
+    (start != end).then(|| src[start .. end].into())
+}
+
+// parse the bindings of an attribute set, starting after the opening brace,
+// returning the bindings and the position of the closing brace
+fn parse_bindings(src: &str, kinds: &[Kind], mut i: usize) -> Option<(Vec<Binding>, usize)> {
+    let bytes = src.as_bytes();
+    let mut bindings = Vec::new();
+
+    loop {
+        i = skip_trivia(src, kinds, i);
+        match bytes.get(i)? {
+            b'}' if kinds[i] == Kind::Code => return Some((bindings, i)),
+            _ if src[i ..].starts_with("inherit")
+                && !bytes.get(i + 7).copied().is_some_and(is_ident) =>
+            {
+                let end = find_end(src, kinds, i)?;
+                // names inherited from another attribute set like `inherit (src) rev;` are ignored
+                if !src[i + 7 .. end].trim_start().starts_with('(') {
+                    let mut j = i + 7;
+                    while j < end {
+                        j = skip_trivia(src, kinds, j);
+                        let start = j;
+                        while j < end && is_ident(bytes[j]) {
+                            j += 1;
+                        }
+                        if start == j {
+                            break;
+                        }
+                        bindings.push(Binding {
+                            key: src[start .. j].into(),
+                            key_span: start .. j,
+                            value_span: start .. j,
+                            value: Value::Inherit,
+                        });
+                    }
+                }
+                i = end + 1;
+            }
+            _ => {
+                let key_start = i;
+                while matches!(bytes.get(i)?, c if is_ident(*c) || *c == b'.') {
+                    i += 1;
+                }
+                let key_span = key_start .. i;
+
+                i = skip_trivia(src, kinds, i);
+                if key_span.is_empty() || bytes.get(i) != Some(&b'=') || kinds[i] != Kind::Code {
+                    return None;
+                }
+
+                let start = skip_trivia(src, kinds, i + 1);
+                let end = find_end(src, kinds, start)?;
+                let value_span = start .. src[.. end].trim_end().len();
+                let text = &src[value_span.clone()];
+
+                bindings.push(Binding {
+                    key: src[key_span.clone()].into(),
+                    key_span,
+                    value: match text {
+                        "true" => Value::Bool(true),
+                        "false" => Value::Bool(false),
+                        _ => parse_string(text)
+                            .map_or_else(|| Value::Expr(text.into()), Value::String),
+                    },
+                    value_span,
+                });
+                i = end + 1;
+            }
+        }
+    }
+}
+
+// names bound to string literals anywhere in the file, like `version = "1.2.3";`
+fn find_vars(src: &str) -> Vars {
+    let kinds = classify(src);
+    let bytes = src.as_bytes();
+    let mut vars = Vars::default();
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if kinds[i] != Kind::Code
+            || !is_ident(bytes[i])
+            || (i != 0 && (is_ident(bytes[i - 1]) || bytes[i - 1] == b'.'))
+        {
+            i += 1;
+            continue;
+        }
+
+        let start = i;
+        while bytes.get(i).copied().is_some_and(is_ident) {
+            i += 1;
+        }
+        let name = &src[start .. i];
+
+        let j = skip_trivia(src, &kinds, i);
+        if bytes.get(j) != Some(&b'=') || kinds[j] != Kind::Code || bytes.get(j + 1) == Some(&b'=')
+        {
+            continue;
+        }
+        let value_start = skip_trivia(src, &kinds, j + 1);
+        let Some(end) = find_end(src, &kinds, value_start) else {
+            continue;
+        };
+        let span = value_start .. src[.. end].trim_end().len();
+        let Some(value) = parse_string(&src[span.clone()]) else {
+            continue;
+        };
+
+        vars.entry(name.into())
+            .and_modify(|var| *var = None)
+            .or_insert(Some(Var { span, value }));
+    }
+
+    vars
+}
+
+// a string made of one variable, like `version` or `"v${version}"`,
+// returning the text before the variable, its name, and the text after it
+fn parse_template(text: &str) -> Option<(&str, &str, &str)> {
+    let is_name = |name: &str| {
+        name.bytes().all(is_ident)
+            && name
+                .bytes()
+                .next()
+                .is_some_and(|c| c.is_ascii_alphabetic() || c == b'_')
+    };
+    if is_name(text) {
+        return Some(("", text, ""));
+    }
+
+    let (prefix, rest) = text
+        .strip_prefix('"')?
+        .strip_suffix('"')?
+        .split_once("${")?;
+    let (name, suffix) = rest.split_once('}')?;
+    let plain = |s: &str| !s.contains(['$', '\\', '"']);
+    (is_name(name) && plain(prefix) && plain(suffix)).then_some((prefix, name, suffix))
+}
+
+// find the semicolon that ends the current binding
+fn find_end(src: &str, kinds: &[Kind], mut i: usize) -> Option<usize> {
+    let bytes = src.as_bytes();
+    let mut depth = 0usize;
+    loop {
+        let c = *bytes.get(i)?;
+        if kinds[i] == Kind::Code {
+            match c {
+                b'(' | b'[' | b'{' => depth += 1,
+                b')' | b']' | b'}' => depth = depth.checked_sub(1)?,
+                b';' if depth == 0 => return Some(i),
+                _ => {}
+            }
+        }
+        i += 1;
+    }
+}
+
+fn skip_trivia(src: &str, kinds: &[Kind], mut i: usize) -> usize {
+    let bytes = src.as_bytes();
+    while bytes
+        .get(i)
+        .is_some_and(|c| kinds[i] == Kind::Comment || c.is_ascii_whitespace())
+    {
+        i += 1;
+    }
+    i
+}
+
+// parse a string literal without interpolation
+fn parse_string(text: &str) -> Option<String> {
+    let mut chars = text.strip_prefix('"')?.chars();
+    let mut s = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return chars.next().is_none().then_some(s),
+            '\\' => match chars.next()? {
+                'n' => s.push('\n'),
+                'r' => s.push('\r'),
+                't' => s.push('\t'),
+                c => s.push(c),
+            },
+            '$' if chars.as_str().starts_with('{') => return None,
+            '$' if chars.as_str().starts_with("${") => {
+                chars.next();
+                s.push_str("${");
+                chars.next();
+            }
+            c => s.push(c),
+        }
+    }
+}
+
+// classify every byte as top level code, a comment, or part of a string
+fn classify(src: &str) -> Vec<Kind> {
+    enum Ctx {
+        Code(usize),
+        Str,
+        IndStr,
+    }
+
+    let bytes = src.as_bytes();
+    let mut kinds = vec![Kind::String; bytes.len()];
+    let mut stack = vec![Ctx::Code(0)];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let rest = &src[i ..];
+        let top = stack.len() == 1;
+        match stack.last_mut() {
+            Some(Ctx::Code(depth)) => {
+                if rest.starts_with('#') || rest.starts_with("/*") {
+                    let len = if rest.starts_with('#') {
+                        rest.find('\n').unwrap_or(rest.len())
+                    } else {
+                        rest[2 ..].find("*/").map_or(rest.len(), |j| j + 4)
+                    };
+                    if top {
+                        kinds[i .. i + len].fill(Kind::Comment);
+                    }
+                    i += len;
+                } else if rest.starts_with('"') {
+                    stack.push(Ctx::Str);
+                    i += 1;
+                } else if rest.starts_with("''") {
+                    stack.push(Ctx::IndStr);
+                    i += 2;
+                } else if rest.starts_with('}') && *depth == 0 && !top {
+                    stack.pop();
+                    i += 1;
+                } else {
+                    match bytes[i] {
+                        b'{' => *depth += 1,
+                        b'}' => *depth = depth.saturating_sub(1),
+                        _ => {}
+                    }
+                    if top {
+                        kinds[i] = Kind::Code;
+                    }
+                    i += 1;
+                }
+            }
+            Some(Ctx::Str) => {
+                if rest.starts_with('\\') || rest.starts_with("$${") {
+                    i += 2;
+                } else if rest.starts_with("${") {
+                    stack.push(Ctx::Code(0));
+                    i += 2;
+                } else if rest.starts_with('"') {
+                    stack.pop();
+                    i += 1;
+                } else {
+                    i += 1;
+                }
+            }
+            Some(Ctx::IndStr) => {
+                if rest.starts_with("'''") || rest.starts_with("''$") {
+                    i += 3;
+                } else if rest.starts_with("''\\") {
+                    i += 4;
+                } else if rest.starts_with("''") {
+                    stack.pop();
+                    i += 2;
+                } else if rest.starts_with("$${") {
+                    i += 3;
+                } else if rest.starts_with("${") {
+                    stack.push(Ctx::Code(0));
+                    i += 2;
+                } else {
+                    i += 1;
+                }
+            }
+            None => unreachable!(),
+        }
+    }
+
+    kinds
+}
+
+fn line_of(src: &str, i: usize) -> usize {
+    src[.. i].matches('\n').count() + 1
+}
+
+fn is_ident(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, b'_' | b'-' | b'\'')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, sync::Arc};
+
+    use tempfile::TempDir;
+
+    use super::{Selection, Value, find_calls, find_vars, run, update_call};
+    use crate::{Inference, cli::FetcherFunction, config::FetcherConfig, prefetch::Fixtures};
+
+    const SRC: &str = r#"{ fetchFromGitHub, fetchurl }:
+
+{
+  # fetchurl { url = "https://example.org"; };
+  src = fetchFromGitHub {
+    owner = "nix-community";
+    repo = "nurl"; # comment; with semicolon
+    tag = "v0.3.0";
+    hash = "sha256-jZ+cCp1THDhfHH5yMmRPjGuthOqsgcF/3OjZ61FMdA4=";
+    postFetch = ''
+      echo "}"
+    '';
+  };
+
+  passthru.foo = pkgs.fetchurl rec {
+    url = "https://example.org/${name}";
+    name = "foo";
+    hash = "";
+  };
+}
+"#;
+
+    #[test]
+    fn calls() {
+        let calls = find_calls(SRC);
+        assert_eq!(calls.len(), 2);
+
+        let call = &calls[0];
+        assert_eq!(call.name, "fetchFromGitHub");
+        assert_eq!(call.attr.as_deref(), Some("src"));
+        assert_eq!(call.lines, 5 .. 14);
+        let keys: Vec<_> = call.bindings.iter().map(|b| b.key.as_str()).collect();
+        assert_eq!(keys, ["owner", "repo", "tag", "hash", "postFetch"]);
+        assert!(matches!(&call.bindings[2].value, Value::String(tag) if tag == "v0.3.0"));
+        assert_eq!(&SRC[call.bindings[2].value_span.clone()], r#""v0.3.0""#);
+        assert!(
+            matches!(&call.bindings[4].value, Value::Expr(expr) if expr.starts_with("''") && expr.ends_with("''"))
+        );
+
+        let call = &calls[1];
+        assert_eq!(call.name, "fetchurl");
+        assert_eq!(call.attr.as_deref(), Some("passthru.foo"));
+        assert!(matches!(&call.bindings[0].value, Value::Expr(_)));
+        assert!(matches!(&call.bindings[2].value, Value::String(hash) if hash.is_empty()));
+    }
+
+    const PACKAGE: &str = r#"{ fetchgit }:
+
+rec {
+  pname = "r";
+  version = "1.2.0";
+
+  src = fetchgit {
+    url = "https://example.org/${pname}.git";
+    tag = "v${version}";
+    hash = "sha256-old";
+  };
+
+  other = fetchgit {
+    url = "https://example.org/other.git";
+    rev = "0000000000000000000000000000000000000000";
+    hash = "";
+  };
+}
+"#;
+
+    fn setup(src: &str) -> (TempDir, FetcherConfig, Inference) {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("default.nix"), src).unwrap();
+        fs::write(
+            dir.path().join("fixtures.json"),
+            r#"{
+  "git https://example.org/other.git 1111111111111111111111111111111111111111 submodules": "sha256-other",
+  "git https://example.org/r.git refs/tags/v1.3.0 submodules": "sha256-new",
+  "ref https://example.org/r.git v1.3.0": "tag"
+}"#,
+        )
+        .unwrap();
+
+        let cfg = FetcherConfig {
+            prefetcher: Arc::new(Fixtures::replay(dir.path().join("fixtures.json")).unwrap()),
+            ..Default::default()
+        };
+        let inference = Inference {
+            fallback: FetcherFunction::Fetchgit,
+            patch: FetcherFunction::Fetchpatch,
+            hosts: Default::default(),
+            detect: false,
+        };
+
+        (dir, cfg, inference)
+    }
+
+    #[test]
+    fn vars() {
+        let vars = find_vars(PACKAGE);
+        let var = vars["version"].as_ref().unwrap();
+        assert_eq!(var.value, "1.2.0");
+        assert_eq!(&PACKAGE[var.span.clone()], r#""1.2.0""#);
+        // bound in both calls
+        assert!(vars["hash"].is_none());
+        assert!(!vars.contains_key("src"));
+    }
+
+    #[test]
+    fn update() {
+        let (dir, cfg, inference) = setup(PACKAGE);
+        let path = dir.path().join("default.nix");
+
+        let failures = run(
+            &path,
+            &Selection::Attr("src".into()),
+            Some("v1.3.0"),
+            &inference,
+            &cfg,
+        )
+        .unwrap();
+        assert_eq!(failures, 0);
+
+        let failures = run(
+            &path,
+            &Selection::Line(14),
+            Some("1111111111111111111111111111111111111111"),
+            &inference,
+            &cfg,
+        )
+        .unwrap();
+        assert_eq!(failures, 0);
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            PACKAGE
+                .replace(r#""1.2.0""#, r#""1.3.0""#)
+                .replace("sha256-old", "sha256-new")
+                .replace(&"0".repeat(40), &"1".repeat(40))
+                .replace(r#"hash = "";"#, r#"hash = "sha256-other";"#),
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let src = r#"{
+  inherited = fetchgit {
+    url = "https://example.org/other.git";
+    inherit rev;
+    hash = "";
+  };
+  missing = fetchgit {
+    url = "https://example.org/other.git";
+    hash = "";
+  };
+  expr = fetchgit {
+    url = "https://example.org/other.git";
+    rev = src.rev;
+    hash = "";
+  };
+}"#;
+        let (_dir, cfg, inference) = setup(src);
+        let vars = find_vars(src);
+
+        let errors: Vec<_> = find_calls(src)
+            .iter()
+            .map(|call| {
+                update_call(call, &vars, Some(&"1".repeat(40)), &inference, &cfg)
+                    .err()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "rev is inherited",
+                "no revision found",
+                "rev is not a string literal",
+            ],
+        );
+    }
+}
@@ -0,0 +1,372 @@
+use std::cmp::Ordering;
+
+/// A version parsed from a tag like `v1.2.3`, `nurl-0.3.0`, or `1.0.0-rc.1`
+#[derive(Debug, PartialEq, Eq)]
+pub struct Version<'a> {
+    numbers: Vec<u64>,
+    suffix: &'a str,
+}
+
+// a and b are short for alpha and beta, like 2.0b1
+const PRERELEASE: [&str; 10] = [
+    "a", "alpha", "b", "beta", "dev", "nightly", "pre", "preview", "rc", "snapshot",
+];
+
+impl<'a> Version<'a> {
+    /// Everything before the first digit is ignored,
+    /// returns `None` if there are no digits
+    pub fn parse(tag: &'a str) -> Option<Self> {
+        let start = tag.find(|c: char| c.is_ascii_digit())?;
+        let mut rest = &tag[start ..];
+        let mut numbers = Vec::new();
+
+        loop {
+            let end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            numbers.push(rest[.. end].parse().ok()?);
+            rest = &rest[end ..];
+
+            match rest.strip_prefix('.') {
+                Some(next) if next.starts_with(|c: char| c.is_ascii_digit()) => rest = next,
+                _ => break,
+            }
+        }
+
+        // build metadata does not affect the version
+        let suffix = rest.split_once('+').map_or(rest, |(suffix, _)| suffix);
+
+        Some(Self { numbers, suffix })
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        self.suffix
+            .to_ascii_lowercase()
+            .split(|c: char| !c.is_ascii_alphabetic())
+            .any(|word| PRERELEASE.contains(&word))
+    }
+}
+
+impl Ord for Version<'_> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_numbers(&self.numbers, &other.numbers)
+            // 1.0.0-rc.1 < 1.0.0
+            .then_with(|| match (self.suffix.is_empty(), other.suffix.is_empty()) {
+                (true, true) => Ordering::Equal,
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                (false, false) => natural_cmp(self.suffix, other.suffix),
+            })
+    }
+}
+
+impl PartialOrd for Version<'_> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// missing components are zeros, so 1.2 == 1.2.0
+fn cmp_numbers(xs: &[u64], ys: &[u64]) -> Ordering {
+    for i in 0 .. xs.len().max(ys.len()) {
+        match xs.get(i).unwrap_or(&0).cmp(ys.get(i).unwrap_or(&0)) {
+            Ordering::Equal => {}
+            ordering => return ordering,
+        }
+    }
+    Ordering::Equal
+}
+
+// compare strings with runs of digits compared numerically, so rc.10 > rc.9
+fn natural_cmp(x: &str, y: &str) -> Ordering {
+    let mut x = x;
+    let mut y = y;
+
+    loop {
+        match (x.chars().next(), y.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(a), Some(b)) if a.is_ascii_digit() && b.is_ascii_digit() => {
+                let (a, x_rest) = split_digits(x);
+                let (b, y_rest) = split_digits(y);
+                let a = a.trim_start_matches('0');
+                let b = b.trim_start_matches('0');
+                match (a.len(), a).cmp(&(b.len(), b)) {
+                    Ordering::Equal => {}
+                    ordering => return ordering,
+                }
+                x = x_rest;
+                y = y_rest;
+            }
+            (Some(a), Some(b)) => {
+                match a.cmp(&b) {
+                    Ordering::Equal => {}
+                    ordering => return ordering,
+                }
+                x = &x[a.len_utf8() ..];
+                y = &y[b.len_utf8() ..];
+            }
+        }
+    }
+}
+
+fn split_digits(s: &str) -> (&str, &str) {
+    s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()))
+}
+
+/// The tag with the highest version, skipping pre-releases unless `prerelease` is true
+pub fn latest<'a>(tags: impl IntoIterator<Item = &'a str>, prerelease: bool) -> Option<&'a str> {
+    max_by_version(tags.into_iter().map(|tag| (tag, tag)), None, prerelease)
+}
+
+/// The item with the highest version that satisfies `req`,
+/// skipping pre-releases unless `prerelease` is true
+pub fn max_by_version<'a, T>(
+    items: impl IntoIterator<Item = (&'a str, T)>,
+    req: Option<&Requirement>,
+    prerelease: bool,
+) -> Option<T> {
+    items
+        .into_iter()
+        .filter_map(|(version, item)| Some((Version::parse(version)?, item)))
+        .filter(|(version, _)| prerelease || !version.is_prerelease())
+        .filter(|(version, _)| req.is_none_or(|req| req.matches(version)))
+        .max_by(|(x, _), (y, _)| x.cmp(y))
+        .map(|(_, item)| item)
+}
+
+/// A version requirement like `^1.4`, `~=2.1`, `~> 1.2`, `>=1.0, <3`, or `1.*`
+///
+/// Bare versions are not requirements, since they are used as exact versions
+pub struct Requirement(Vec<(Op, Vec<u64>)>);
+
+#[derive(Clone, Copy)]
+enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    // matches versions starting with the components, like 1.2.*
+    Prefix,
+}
+
+impl Requirement {
+    pub fn parse(req: &str) -> Option<Self> {
+        let mut comparators = Vec::new();
+
+        for comparator in req.split(',') {
+            let comparator = comparator.trim();
+            let (op, version) = [
+                ("~=", None),
+                ("~>", None),
+                ("==", Some(Op::Eq)),
+                ("!=", Some(Op::Ne)),
+                (">=", Some(Op::Ge)),
+                ("<=", Some(Op::Le)),
+                ("^", None),
+                ("~", None),
+                ("=", Some(Op::Eq)),
+                (">", Some(Op::Gt)),
+                ("<", Some(Op::Lt)),
+            ]
+            .into_iter()
+            .find_map(|(prefix, op)| {
+                Some(((prefix, op), comparator.strip_prefix(prefix)?.trim_start()))
+            })
+            // 1.2.* is short for =1.2.*
+            .or_else(|| {
+                comparator
+                    .ends_with('*')
+                    .then_some((("=", Some(Op::Eq)), comparator))
+            })?;
+
+            let (version, wildcard) = match version.strip_suffix(".*") {
+                Some(version) => (version, true),
+                None => (version, version == "*"),
+            };
+            let numbers = if version == "*" {
+                Vec::new()
+            } else {
+                version
+                    .strip_prefix('v')
+                    .unwrap_or(version)
+                    .split('.')
+                    .map(|n| n.parse().ok())
+                    .collect::<Option<Vec<u64>>>()?
+            };
+
+            match op {
+                (_, Some(Op::Eq)) if wildcard => comparators.push((Op::Prefix, numbers)),
+                (_, Some(op)) if !wildcard => comparators.push((op, numbers)),
+                (_, Some(_)) => return None,
+
+                // ^1.2.3 := >=1.2.3, <2.0.0 and ^0.2.3 := >=0.2.3, <0.3.0
+                ("^", _) => {
+                    let len = numbers
+                        .iter()
+                        .position(|&n| n != 0)
+                        .map_or(numbers.len(), |i| i + 1);
+                    comparators.push((Op::Lt, bump(&numbers, len)?));
+                    comparators.push((Op::Ge, numbers));
+                }
+
+                // ~1.2.3 := >=1.2.3, <1.3.0 and ~1 := >=1, <2
+                ("~", _) => {
+                    comparators.push((Op::Lt, bump(&numbers, numbers.len().min(2))?));
+                    comparators.push((Op::Ge, numbers));
+                }
+
+                // ~=1.2.3 := >=1.2.3, <1.3 and ~=1.2 := >=1.2, <2
+                _ => {
+                    if numbers.len() < 2 {
+                        return None;
+                    }
+                    comparators.push((Op::Lt, bump(&numbers, numbers.len() - 1)?));
+                    comparators.push((Op::Ge, numbers));
+                }
+            }
+        }
+
+        Some(Self(comparators))
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        self.0.iter().all(|(op, numbers)| {
+            let ordering = cmp_numbers(&version.numbers, numbers);
+            match op {
+                Op::Eq => ordering.is_eq(),
+                Op::Ne => ordering.is_ne(),
+                Op::Gt => ordering.is_gt(),
+                Op::Ge => ordering.is_ge(),
+                Op::Lt => ordering.is_lt(),
+                Op::Le => ordering.is_le(),
+                Op::Prefix => version.numbers.starts_with(numbers),
+            }
+        })
+    }
+
+    /// The highest version that satisfies the requirement,
+    /// skipping pre-releases unless `prerelease` is true
+    pub fn latest<'a>(
+        &self,
+        versions: impl IntoIterator<Item = &'a str>,
+        prerelease: bool,
+    ) -> Option<&'a str> {
+        max_by_version(
+            versions.into_iter().map(|version| (version, version)),
+            Some(self),
+            prerelease,
+        )
+    }
+}
+
+// the first `len` components with the last one incremented
+fn bump(numbers: &[u64], len: usize) -> Option<Vec<u64>> {
+    let mut numbers = numbers.get(.. len.max(1))?.to_vec();
+    *numbers.last_mut()? += 1;
+    Some(numbers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Requirement, Version, latest, max_by_version};
+
+    #[test]
+    fn order() {
+        let versions = [
+            "v0.9",
+            "v1.0.0-alpha",
+            "v1.0.0-beta.2",
+            "v1.0.0-beta.10",
+            "v1.0.0-rc1",
+            "1.0",
+            "v1.0.1",
+            "v1.2",
+            "v1.10.0",
+        ]
+        .map(|tag| Version::parse(tag).unwrap());
+
+        for xs in versions.windows(2) {
+            assert!(xs[0] < xs[1], "{:?} < {:?}", xs[0], xs[1]);
+        }
+    }
+
+    #[test]
+    fn prerelease() {
+        for tag in ["1.0.0-rc.1", "v2.0b1", "3.0a2", "4.0-dev", "v5.0.0-Beta"] {
+            assert!(Version::parse(tag).unwrap().is_prerelease(), "{tag}");
+        }
+        for tag in ["1.0.0", "v2.0", "nurl-3.0", "4.0+build.5", "5.0-express"] {
+            assert!(!Version::parse(tag).unwrap().is_prerelease(), "{tag}");
+        }
+    }
+
+    #[test]
+    fn latest_tag() {
+        let tags = ["v0.3.9", "v0.3.10", "v0.4.0-rc.1", "nightly"];
+        assert_eq!(latest(tags, false), Some("v0.3.10"));
+        assert_eq!(latest(tags, true), Some("v0.4.0-rc.1"));
+    }
+
+    #[test]
+    fn requirements() {
+        let versions = [
+            "0.1.0",
+            "0.2.0",
+            "0.2.5",
+            "1.0.0",
+            "1.4.0",
+            "1.4.7",
+            "1.5.0",
+            "2.0.0-rc.1",
+            "2.1.0",
+            "3.0.0",
+        ];
+
+        for (req, expected) in [
+            ("^1.4", Some("1.5.0")),
+            ("^0.2", Some("0.2.5")),
+            ("~1.4", Some("1.4.7")),
+            ("~=1.4", Some("1.5.0")),
+            ("~=1.4.0", Some("1.4.7")),
+            ("~> 2.0", Some("2.1.0")),
+            ("<3", Some("2.1.0")),
+            (">=1, <2, !=1.5.0", Some("1.4.7")),
+            ("1.4.*", Some("1.4.7")),
+            ("=0.1.0", Some("0.1.0")),
+            (">3", None),
+        ] {
+            assert_eq!(
+                Requirement::parse(req).unwrap().latest(versions, false),
+                expected,
+                "{req}",
+            );
+        }
+    }
+
+    #[test]
+    fn exact_versions() {
+        for version in ["1.4", "v1.4.0", "main", "1.*.3"] {
+            assert!(Requirement::parse(version).is_none(), "{version}");
+        }
+    }
+
+    #[test]
+    fn monorepo_tags() {
+        let tags = [
+            "cli-v2.4.0",
+            "lib-v1.9.0",
+            "cli-v3.0.0",
+            "lib-v1.10.2",
+            "lib-v2.0.0",
+        ];
+        let lib = tags
+            .into_iter()
+            .filter_map(|tag| Some((tag.strip_prefix("lib-")?, tag)));
+        let req = Requirement::parse(">=1.0, <2").unwrap();
+        assert_eq!(max_by_version(lib, Some(&req), false), Some("lib-v1.10.2"));
+    }
+}